@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use argh::FromArgs;
@@ -8,6 +9,7 @@ use tinywasm::{types::WasmValue, Module};
 
 use crate::args::to_wasm_args;
 mod args;
+mod snapshot;
 mod util;
 
 #[cfg(feature = "wat")]
@@ -28,6 +30,7 @@ struct TinyWasmCli {
 #[argh(subcommand)]
 enum TinyWasmSubcommand {
     Run(Run),
+    Snapshot(Snapshot),
 }
 
 enum Engine {
@@ -64,6 +67,31 @@ struct Run {
     /// engine to use
     #[argh(option, short = 'e', default = "Engine::Main")]
     engine: Engine,
+
+    /// write a profile to the given file (folded stacks, or pprof if the file ends with `.pb`)
+    #[argh(option)]
+    profile: Option<String>,
+
+    /// number of instructions between profiler samples
+    #[argh(option, default = "1000")]
+    profile_interval: u64,
+}
+
+#[derive(FromArgs)]
+/// pre-initialize a wasm file and write the resulting module
+#[argh(subcommand, name = "snapshot")]
+struct Snapshot {
+    /// wasm file to pre-initialize
+    #[argh(positional)]
+    wasm_file: String,
+
+    /// output file (`.twasm` or `.wasm`)
+    #[argh(option, short = 'o')]
+    output: String,
+
+    /// exported function to run for initialization
+    #[argh(option, short = 'f', default = "\"wizer.initialize\".to_string()")]
+    init_func: String,
 }
 
 fn main() -> Result<()> {
@@ -81,36 +109,70 @@ fn main() -> Result<()> {
     let cwd = std::env::current_dir()?;
 
     match args.nested {
-        TinyWasmSubcommand::Run(Run { wasm_file, engine, args, func }) => {
+        TinyWasmSubcommand::Run(Run { wasm_file, engine, args, func, profile, profile_interval }) => {
             debug!("args: {:?}", args);
 
-            let path = cwd.join(wasm_file.clone());
-            let module = match wasm_file.ends_with(".wat") {
-                #[cfg(feature = "wat")]
-                true => {
-                    let wat = std::fs::read_to_string(path)?;
-                    let wasm = wat::wat2wasm(&wat);
-                    tinywasm::Module::parse_bytes(&wasm)?
-                }
-                #[cfg(not(feature = "wat"))]
-                true => return Err(eyre::eyre!("wat support is not enabled in this build")),
-                false => tinywasm::Module::parse_file(path)?,
-            };
+            let module = tinywasm::Module::parse_bytes(&read_wasm(&cwd.join(&wasm_file))?)?;
 
             match engine {
-                Engine::Main => run(module, func, &to_wasm_args(args)),
+                Engine::Main => {
+                    run(module, func, &to_wasm_args(args), profile.map(|p| (cwd.join(p), profile_interval)))
+                }
             }
         }
+        TinyWasmSubcommand::Snapshot(Snapshot { wasm_file, output, init_func }) => {
+            let wasm = read_wasm(&cwd.join(wasm_file))?;
+            let module = tinywasm::Module::parse_bytes(&wasm)?.preinitialize(None, &init_func)?;
+
+            let path = cwd.join(output);
+            let data = match path.extension().is_some_and(|ext| ext == "twasm") {
+                true => module.serialize_twasm().to_vec(),
+                false => snapshot::encode_wasm(&wasm, &module)?,
+            };
+            std::fs::write(&path, data)?;
+            info!("pre-initialized module written to {}", path.display());
+            Ok(())
+        }
+    }
+}
+
+fn read_wasm(path: &Path) -> Result<Vec<u8>> {
+    match path.extension().is_some_and(|ext| ext == "wat") {
+        #[cfg(feature = "wat")]
+        true => Ok(wat::wat2wasm(&std::fs::read_to_string(path)?)),
+        #[cfg(not(feature = "wat"))]
+        true => Err(eyre::eyre!("wat support is not enabled in this build")),
+        false => Ok(std::fs::read(path)?),
     }
 }
 
-fn run(module: Module, func: Option<String>, args: &[WasmValue]) -> Result<()> {
+fn run(module: Module, func: Option<String>, args: &[WasmValue], profile: Option<(PathBuf, u64)>) -> Result<()> {
     let mut store = tinywasm::Store::default();
-    let instance = module.instantiate(&mut store, None)?;
+    if let Some((_, interval)) = profile {
+        store.start_profiling(interval);
+    }
+
+    let res = call(&mut store, module, func, args);
+
+    // also write the profile if execution failed
+    if let (Some((path, _)), Some(profile)) = (profile, store.stop_profiling()) {
+        let data = match path.extension().is_some_and(|ext| ext == "pb") {
+            true => profile.to_pprof(),
+            false => profile.to_folded().into_bytes(),
+        };
+        std::fs::write(&path, data)?;
+        info!("profile written to {}", path.display());
+    }
+
+    res
+}
+
+fn call(store: &mut tinywasm::Store, module: Module, func: Option<String>, args: &[WasmValue]) -> Result<()> {
+    let instance = module.instantiate(store, None)?;
 
     if let Some(func) = func {
-        let func = instance.exported_func_untyped(&store, &func)?;
-        let res = func.call(&mut store, args)?;
+        let func = instance.exported_func_untyped(store, &func)?;
+        let res = func.call(store, args)?;
         info!("{res:?}");
     }
 
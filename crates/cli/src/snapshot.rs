@@ -0,0 +1,156 @@
+use eyre::{eyre, Result};
+use tinywasm::types::{ConstInstruction, DataKind, ExternalKind, TinyWasmModule, ValType};
+use wasm_encoder::{
+    AbstractHeapType, ConstExpr, DataCountSection, DataSection, ExportKind, ExportSection, GlobalSection, GlobalType,
+    HeapType, MemorySection, MemoryType, RawSection,
+};
+use wasmparser::Payload;
+
+/// Re-encode the original WebAssembly binary with the state of a pre-initialized module
+///
+/// Sections affected by pre-initialization are replaced, all other sections are copied unchanged.
+pub fn encode_wasm(original: &[u8], module: &TinyWasmModule) -> Result<Vec<u8>> {
+    let mut out = wasm_encoder::Module::new();
+    let mut memories = module.memory_types.iter();
+    let mut data_emitted = false;
+
+    for payload in wasmparser::Parser::new(0).parse_all(original) {
+        let payload = payload?;
+        match &payload {
+            Payload::StartSection { .. } => {}
+            Payload::GlobalSection(_) => {
+                let mut section = GlobalSection::new();
+                for global in module.globals.iter() {
+                    let ty = GlobalType { val_type: val_type(global.ty.ty), mutable: global.ty.mutable, shared: false };
+                    section.global(ty, &const_expr(&global.init));
+                }
+                out.section(&section);
+            }
+            Payload::MemorySection(reader) => {
+                let mut section = MemorySection::new();
+                for memory in reader.clone() {
+                    let memory = memory?;
+                    let ty = memories.next().ok_or_else(|| eyre!("memory count mismatch"))?;
+                    section.memory(MemoryType {
+                        minimum: ty.page_count_initial(),
+                        maximum: memory.maximum,
+                        memory64: memory.memory64,
+                        shared: memory.shared,
+                        page_size_log2: memory.page_size_log2,
+                    });
+                }
+                out.section(&section);
+            }
+            Payload::ExportSection(_) => {
+                let mut section = ExportSection::new();
+                for export in module.exports.iter() {
+                    let kind = match export.kind {
+                        ExternalKind::Func => ExportKind::Func,
+                        ExternalKind::Table => ExportKind::Table,
+                        ExternalKind::Memory => ExportKind::Memory,
+                        ExternalKind::Global => ExportKind::Global,
+                    };
+                    section.export(&export.name, kind, export.index);
+                }
+                out.section(&section);
+            }
+            Payload::DataCountSection { .. } => {
+                out.section(&DataCountSection { count: module.data.len() as u32 });
+            }
+            Payload::DataSection(_) => {
+                out.section(&data_section(module));
+                data_emitted = true;
+            }
+            Payload::End(_) if !data_emitted && !module.data.is_empty() => {
+                out.section(&data_section(module));
+            }
+            payload => {
+                if let Some((id, range)) = payload.as_section() {
+                    out.section(&RawSection { id, data: &original[range] });
+                }
+            }
+        }
+    }
+
+    Ok(out.finish())
+}
+
+fn data_section(module: &TinyWasmModule) -> DataSection {
+    let mut section = DataSection::new();
+    for data in module.data.iter() {
+        match &data.kind {
+            DataKind::Active { mem, offset } => section.active(*mem, &const_expr(offset), data.data.iter().copied()),
+            DataKind::Passive => section.passive(data.data.iter().copied()),
+        };
+    }
+    section
+}
+
+fn val_type(ty: ValType) -> wasm_encoder::ValType {
+    match ty {
+        ValType::I32 => wasm_encoder::ValType::I32,
+        ValType::I64 => wasm_encoder::ValType::I64,
+        ValType::F32 => wasm_encoder::ValType::F32,
+        ValType::F64 => wasm_encoder::ValType::F64,
+        ValType::V128 => wasm_encoder::ValType::V128,
+        ValType::RefFunc => wasm_encoder::ValType::FUNCREF,
+        ValType::RefExtern => wasm_encoder::ValType::EXTERNREF,
+    }
+}
+
+fn const_expr(instr: &ConstInstruction) -> ConstExpr {
+    match instr {
+        ConstInstruction::I32Const(v) => ConstExpr::i32_const(*v),
+        ConstInstruction::I64Const(v) => ConstExpr::i64_const(*v),
+        ConstInstruction::F32Const(v) => ConstExpr::f32_const(*v),
+        ConstInstruction::F64Const(v) => ConstExpr::f64_const(*v),
+        ConstInstruction::GlobalGet(idx) => ConstExpr::global_get(*idx),
+        ConstInstruction::RefFunc(idx) => ConstExpr::ref_func(*idx),
+        ConstInstruction::RefNull(ty) => {
+            let ty = match ty {
+                ValType::RefExtern => AbstractHeapType::Extern,
+                _ => AbstractHeapType::Func,
+            };
+            ConstExpr::ref_null(HeapType::Abstract { shared: false, ty })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wat"))]
+mod tests {
+    use super::encode_wasm;
+    use crate::wat::wat2wasm;
+    use eyre::Result;
+    use tinywasm::{Module, Store};
+
+    #[test]
+    fn test_preinitialize_roundtrip() -> Result<()> {
+        let wasm = wat2wasm(
+            r#"(module
+                (memory (export "memory") 1 4)
+                (global $counter (export "counter") (mut i32) (i32.const 0))
+                (data (i32.const 8) "tiny")
+                (func $start (global.set $counter (i32.const 1)))
+                (start $start)
+                (func (export "init")
+                    (drop (memory.grow (i32.const 1)))
+                    (i32.store (i32.const 70000) (i32.const 0x11223344))
+                    (global.set $counter (i32.add (global.get $counter) (i32.const 41)))))"#,
+        );
+
+        let module = Module::parse_bytes(&wasm)?.preinitialize(None, "init")?;
+        let encoded = encode_wasm(&wasm, &module)?;
+
+        let module = Module::parse_bytes(&encoded)?;
+        assert!(module.exports().all(|export| export.name != "init"));
+
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, None)?;
+        assert_eq!(instance.exported_global(&mut store, "counter")?.get::<i32>()?, 42);
+
+        let memory = instance.exported_memory(&mut store, "memory")?;
+        assert_eq!(memory.load(8, 4)?, b"tiny");
+        assert_eq!(memory.load(70000, 4)?, 0x11223344i32.to_le_bytes());
+        Ok(())
+    }
+}
@@ -164,9 +164,26 @@ pub(crate) fn convert_module_export(export: wasmparser::Export<'_>) -> Result<Ex
     Ok(Export { index: export.index, name: Box::from(export.name), kind })
 }
 
+pub(crate) fn convert_module_func_names(reader: wasmparser::NameSectionReader<'_>) -> Result<Vec<(u32, Box<str>)>> {
+    let mut names = Vec::new();
+    for subsection in reader {
+        if let wasmparser::Name::Function(map) = subsection? {
+            for naming in map {
+                let naming = naming?;
+                names.push((naming.index, naming.name.into()));
+            }
+        }
+    }
+
+    names.sort_by_key(|(idx, _)| *idx);
+    names.dedup_by_key(|(idx, _)| *idx);
+    Ok(names)
+}
+
 pub(crate) fn convert_module_code(
     func: wasmparser::FunctionBody<'_>,
     mut validator: FuncValidator<ValidatorResources>,
+    record_offsets: bool,
 ) -> Result<(Code, FuncValidatorAllocations)> {
     let locals_reader = func.get_locals_reader()?;
     let count = locals_reader.get_count();
@@ -203,8 +220,8 @@ pub(crate) fn convert_module_code(
         }
     }
 
-    let (body, allocations) = process_operators_and_validate(validator, func, local_addr_map)?;
-    Ok(((body, local_counts), allocations))
+    let (body, offsets, allocations) = process_operators_and_validate(validator, func, local_addr_map, record_offsets)?;
+    Ok(((body, offsets, local_counts), allocations))
 }
 
 pub(crate) fn convert_module_type(ty: wasmparser::RecGroup) -> Result<FuncType> {
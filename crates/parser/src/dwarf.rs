@@ -0,0 +1,114 @@
+use crate::log;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::{boxed::Box, vec::Vec};
+use gimli::{ColumnType, Dwarf, EndianSlice, LittleEndian, SectionId};
+use tinywasm_types::{LineRow, LineTable};
+
+type DwarfReader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// Build a [`LineTable`] from the DWARF custom sections of a module
+///
+/// DWARF addresses are relative to the start of the code section, so they are converted to module offsets.
+/// Malformed debug information is ignored, since it shouldn't prevent the module from being used.
+pub(crate) fn read_line_table(sections: &BTreeMap<String, Vec<u8>>, code_section_offset: usize) -> Option<LineTable> {
+    if !sections.contains_key(SectionId::DebugLine.name()) {
+        return None;
+    }
+
+    match try_read_line_table(sections, code_section_offset as u64) {
+        Ok(table) if !table.rows.is_empty() => Some(table),
+        Ok(_) => None,
+        Err(_e) => {
+            log::error!("ignoring invalid DWARF debug information: {}", _e);
+            None
+        }
+    }
+}
+
+fn try_read_line_table(sections: &BTreeMap<String, Vec<u8>>, code_section_offset: u64) -> gimli::Result<LineTable> {
+    let dwarf = Dwarf::load(|id: SectionId| -> gimli::Result<DwarfReader<'_>> {
+        let data = sections.get(id.name()).map_or(&[][..], Vec::as_slice);
+        Ok(EndianSlice::new(data, LittleEndian))
+    })?;
+
+    let mut files = Vec::new();
+    let mut file_indices = BTreeMap::new();
+    let mut rows = Vec::new();
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+
+        let mut program_rows = program.rows();
+        while let Some((header, row)) = program_rows.next_row()? {
+            let file = match row.file(header) {
+                Some(file) => {
+                    let path = file_path(&dwarf, &unit, header, file)?;
+                    *file_indices.entry(path).or_insert_with_key(|path: &String| {
+                        files.push(Box::from(path.as_str()));
+                        files.len() as u32 - 1
+                    })
+                }
+                None => u32::MAX,
+            };
+
+            let Ok(address) = (row.address() + code_section_offset).try_into() else {
+                continue;
+            };
+
+            rows.push(LineRow {
+                address,
+                file,
+                line: row.line().map_or(0, |line| line.get() as u32),
+                column: match row.column() {
+                    ColumnType::LeftEdge => 0,
+                    ColumnType::Column(column) => column.get() as u32,
+                },
+                end_sequence: row.end_sequence(),
+            });
+        }
+    }
+
+    Ok(LineTable::new(files.into_boxed_slice(), rows))
+}
+
+fn file_path<'a>(
+    dwarf: &Dwarf<DwarfReader<'a>>,
+    unit: &gimli::Unit<DwarfReader<'a>>,
+    header: &gimli::LineProgramHeader<DwarfReader<'a>>,
+    file: &gimli::FileEntry<DwarfReader<'a>>,
+) -> gimli::Result<String> {
+    let name = dwarf.attr_string(unit, file.path_name())?.to_string_lossy().to_string();
+    if name.starts_with('/') {
+        return Ok(name);
+    }
+
+    let mut path = match file.directory(header) {
+        Some(dir) => dwarf.attr_string(unit, dir)?.to_string_lossy().to_string(),
+        None => String::new(),
+    };
+
+    // relative directories are relative to the compilation directory
+    if !path.starts_with('/') {
+        if let Some(comp_dir) = &unit.comp_dir {
+            let comp_dir = comp_dir.to_string_lossy();
+            if !comp_dir.is_empty() && path != comp_dir {
+                path = if path.is_empty() { comp_dir.to_string() } else { alloc::format!("{comp_dir}/{path}") };
+            }
+        }
+    }
+
+    if path.is_empty() {
+        return Ok(name);
+    }
+
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(&name);
+    Ok(path)
+}
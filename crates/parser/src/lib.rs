@@ -30,6 +30,8 @@ pub(crate) mod log {
 }
 
 mod conversion;
+#[cfg(feature = "dwarf")]
+mod dwarf;
 mod error;
 mod module;
 mod visit;
@@ -41,12 +43,24 @@ pub use tinywasm_types::TinyWasmModule;
 
 /// A WebAssembly parser
 #[derive(Default, Debug)]
-pub struct Parser {}
+pub struct Parser {
+    record_offsets: bool,
+}
 
 impl Parser {
     /// Create a new parser instance
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Record the original byte offset of every instruction
+    ///
+    /// The offsets are stored in [`tinywasm_types::WasmFunction::offsets`] and are relative to the start of the
+    /// module, matching the positions shown by `wasm-objdump -d`.
+    /// This is always enabled for modules with DWARF line information if the `dwarf` feature is enabled.
+    pub fn with_offsets(mut self, enabled: bool) -> Self {
+        self.record_offsets = enabled;
+        self
     }
 
     fn create_validator() -> Validator {
@@ -87,7 +101,7 @@ impl Parser {
     pub fn parse_module_bytes(&self, wasm: impl AsRef<[u8]>) -> Result<TinyWasmModule> {
         let wasm = wasm.as_ref();
         let mut validator = Self::create_validator();
-        let mut reader = ModuleReader::new();
+        let mut reader = ModuleReader::new(self.record_offsets);
 
         for payload in wasmparser::Parser::new(0).parse_all(wasm) {
             reader.process_payload(payload?, &mut validator)?;
@@ -117,7 +131,7 @@ impl Parser {
         use alloc::format;
 
         let mut validator = Self::create_validator();
-        let mut reader = ModuleReader::new();
+        let mut reader = ModuleReader::new(self.record_offsets);
         let mut buffer = alloc::vec::Vec::new();
         let mut parser = wasmparser::Parser::new(0);
         let mut eof = false;
@@ -8,7 +8,7 @@ use tinywasm_types::{
 };
 use wasmparser::{FuncValidatorAllocations, Payload, Validator};
 
-pub(crate) type Code = (Box<[Instruction]>, ValueCounts);
+pub(crate) type Code = (Box<[Instruction]>, Box<[u32]>, ValueCounts);
 
 #[derive(Default)]
 pub(crate) struct ModuleReader {
@@ -27,11 +27,19 @@ pub(crate) struct ModuleReader {
     pub(crate) data: Vec<Data>,
     pub(crate) elements: Vec<Element>,
     pub(crate) end_reached: bool,
+    pub(crate) func_names: Vec<(u32, Box<str>)>,
+
+    pub(crate) keep_offsets: bool,
+    pub(crate) record_offsets: bool,
+    pub(crate) code_section_offset: usize,
+    #[cfg(feature = "dwarf")]
+    pub(crate) debug_sections: alloc::collections::BTreeMap<alloc::string::String, Vec<u8>>,
 }
 
 impl ModuleReader {
-    pub(crate) fn new() -> ModuleReader {
-        Self::default()
+    pub(crate) fn new(keep_offsets: bool) -> ModuleReader {
+        // offsets are also needed to resolve source locations
+        Self { keep_offsets, record_offsets: keep_offsets || cfg!(feature = "dwarf"), ..Self::default() }
     }
 
     pub(crate) fn process_payload(&mut self, payload: Payload<'_>, validator: &mut Validator) -> Result<()> {
@@ -130,13 +138,15 @@ impl ModuleReader {
                     return Err(ParseError::DuplicateSection("Code section".into()));
                 }
                 self.code.reserve(count as usize);
+                self.code_section_offset = range.start;
                 validator.code_section_start(count, &range)?;
             }
             CodeSectionEntry(function) => {
                 debug!("Found code section entry");
                 let v = validator.code_section_entry(&function)?;
                 let func_validator = v.into_validator(self.func_validator_allocations.take().unwrap_or_default());
-                let (code, allocations) = conversion::convert_module_code(function, func_validator)?;
+                let (code, allocations) =
+                    conversion::convert_module_code(function, func_validator, self.record_offsets)?;
                 self.code.push(code);
                 self.func_validator_allocations = Some(allocations);
             }
@@ -168,10 +178,26 @@ impl ModuleReader {
                 validator.end(offset)?;
                 self.end_reached = true;
             }
-            CustomSection(reader) => {
-                debug!("Found custom section");
-                debug!("Skipping custom section: {:?}", reader.name());
+            #[cfg(feature = "dwarf")]
+            CustomSection(reader) if reader.name().starts_with(".debug_") => {
+                debug!("Found debug section: {:?}", reader.name());
+                self.debug_sections.insert(reader.name().to_string(), reader.data().to_vec());
             }
+            CustomSection(reader) => match reader.as_known() {
+                wasmparser::KnownCustom::Name(names) => {
+                    debug!("Found name section");
+
+                    // the name section is only informational, so errors are ignored
+                    match conversion::convert_module_func_names(names) {
+                        Ok(names) => self.func_names = names,
+                        Err(_e) => crate::log::error!("ignoring invalid name section: {}", _e),
+                    }
+                }
+                _ => {
+                    debug!("Found custom section");
+                    debug!("Skipping custom section: {:?}", reader.name());
+                }
+            },
             UnknownSection { .. } => return Err(ParseError::UnsupportedSection("Unknown section".into())),
             section => return Err(ParseError::UnsupportedSection(format!("Unsupported section: {section:?}"))),
         };
@@ -189,11 +215,18 @@ impl ModuleReader {
             return Err(ParseError::Other("Code and code type address count mismatch".to_string()));
         }
 
+        #[cfg(feature = "dwarf")]
+        let line_table = crate::dwarf::read_line_table(&self.debug_sections, self.code_section_offset);
+        #[cfg(not(feature = "dwarf"))]
+        let line_table = None;
+
+        let keep_offsets = self.keep_offsets || line_table.is_some();
+
         let funcs = self
             .code
             .into_iter()
             .zip(self.code_type_addrs)
-            .map(|((instructions, locals), ty_idx)| {
+            .map(|((instructions, offsets, locals), ty_idx)| {
                 let mut params = ValueCountsSmall::default();
                 let ty = self.func_types.get(ty_idx as usize).expect("No func type for func, this is a bug").clone();
                 for param in &ty.params {
@@ -204,7 +237,8 @@ impl ModuleReader {
                         ValType::RefExtern | ValType::RefFunc => params.cref += 1,
                     }
                 }
-                WasmFunction { instructions, locals, params, ty }
+                let offsets = if keep_offsets { offsets } else { Box::default() };
+                WasmFunction { instructions, offsets, locals, params, ty }
             })
             .collect::<Vec<_>>()
             .into_boxed_slice();
@@ -223,6 +257,8 @@ impl ModuleReader {
             exports: self.exports.into_boxed_slice(),
             elements: self.elements.into_boxed_slice(),
             memory_types: self.memory_types.into_boxed_slice(),
+            line_table,
+            func_names: self.func_names.into_boxed_slice(),
         })
     }
 }
@@ -11,6 +11,7 @@ macro_rules! validate_then_visit {
     ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => {$(
         fn $visit(&mut self $($(,$arg: $argty)*)?) -> Self::Output {
             self.1.$visit($($($arg.clone()),*)?);
+            self.1.record_offset(self.0);
             self.1.validator_visitor(self.0).$visit($($($arg),*)?)?;
             Ok(())
         }
@@ -22,14 +23,17 @@ impl<'a, R: WasmModuleResources> VisitOperator<'a> for ValidateThenVisit<'_, R>
     wasmparser::for_each_operator!(validate_then_visit);
 }
 
+type ProcessedOperators = (Box<[Instruction]>, Box<[u32]>, FuncValidatorAllocations);
+
 pub(crate) fn process_operators_and_validate<R: WasmModuleResources>(
     validator: FuncValidator<R>,
     body: FunctionBody<'_>,
     local_addr_map: Vec<u32>,
-) -> Result<(Box<[Instruction]>, FuncValidatorAllocations)> {
+    record_offsets: bool,
+) -> Result<ProcessedOperators> {
     let mut reader = body.get_operators_reader()?;
     let remaining = reader.get_binary_reader().bytes_remaining();
-    let mut builder = FunctionBuilder::new(remaining, validator, local_addr_map);
+    let mut builder = FunctionBuilder::new(remaining, validator, local_addr_map, record_offsets);
 
     while !reader.eof() {
         reader.visit_operator(&mut ValidateThenVisit(reader.original_position(), &mut builder))??;
@@ -40,7 +44,8 @@ pub(crate) fn process_operators_and_validate<R: WasmModuleResources>(
         return Err(builder.errors.remove(0));
     }
 
-    Ok((builder.instructions.into_boxed_slice(), builder.validator.into_allocations()))
+    let offsets = builder.offsets.unwrap_or_default().into_boxed_slice();
+    Ok((builder.instructions.into_boxed_slice(), offsets, builder.validator.into_allocations()))
 }
 
 macro_rules! define_operands {
@@ -78,6 +83,7 @@ macro_rules! define_mem_operands {
 pub(crate) struct FunctionBuilder<R: WasmModuleResources> {
     validator: FuncValidator<R>,
     instructions: Vec<Instruction>,
+    offsets: Option<Vec<u32>>,
     label_ptrs: Vec<usize>,
     local_addr_map: Vec<u32>,
     errors: Vec<crate::ParseError>,
@@ -97,16 +103,30 @@ impl<R: WasmModuleResources> FunctionBuilder<R> {
 }
 
 impl<R: WasmModuleResources> FunctionBuilder<R> {
-    pub(crate) fn new(instr_capacity: usize, validator: FuncValidator<R>, local_addr_map: Vec<u32>) -> Self {
+    pub(crate) fn new(
+        instr_capacity: usize,
+        validator: FuncValidator<R>,
+        local_addr_map: Vec<u32>,
+        record_offsets: bool,
+    ) -> Self {
         Self {
             validator,
             local_addr_map,
             instructions: Vec::with_capacity(instr_capacity),
+            offsets: record_offsets.then(|| Vec::with_capacity(instr_capacity)),
             label_ptrs: Vec::with_capacity(256),
             errors: Vec::new(),
         }
     }
 
+    // assign the offset of the current operator to all instructions it was lowered to
+    // fused instructions keep the offset of the first operator
+    fn record_offset(&mut self, offset: usize) {
+        if let Some(offsets) = &mut self.offsets {
+            offsets.resize(self.instructions.len(), offset as u32);
+        }
+    }
+
     fn unsupported(&mut self, name: &str) {
         self.errors.push(crate::ParseError::UnsupportedOperator(name.to_string()));
     }
@@ -0,0 +1,200 @@
+use alloc::vec::Vec;
+use core::fmt::Display;
+use tinywasm_types::{ModuleInstanceAddr, SourceLocation};
+
+/// A WebAssembly call stack captured when an error occurred
+///
+/// Frames are ordered from the innermost (the function that trapped) to the outermost call.
+/// See [`crate::Store::last_backtrace`]
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+    pub(crate) frames: Vec<BacktraceFrame>,
+}
+
+/// A single frame of a [`Backtrace`]
+#[derive(Debug, Clone)]
+pub struct BacktraceFrame {
+    /// The module instance that owns the function
+    pub module_addr: ModuleInstanceAddr,
+
+    /// The index of the function in its module's function index space
+    pub func_index: Option<u32>,
+
+    /// The index of the executing instruction in the function's bytecode
+    pub instr_index: usize,
+
+    /// The byte offset of the executing instruction in the original module, if recorded
+    pub code_offset: Option<u32>,
+
+    /// The location in the guest's source code, if the module contains DWARF line information
+    pub location: Option<SourceLocation>,
+}
+
+impl Backtrace {
+    /// Get the frames of the backtrace, innermost first
+    pub fn frames(&self) -> &[BacktraceFrame] {
+        &self.frames
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "{i:>4}: {frame}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for BacktraceFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.func_index {
+            Some(idx) => write!(f, "module[{}]::func[{idx}]", self.module_addr)?,
+            None => write!(f, "module[{}]::<unknown>", self.module_addr)?,
+        }
+
+        if let Some(offset) = self.code_offset {
+            write!(f, " @ {offset:#x}")?;
+        }
+
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod backtrace_tests {
+    use crate::test_util::module;
+    use crate::{Error, Extern, FuncContext, Imports, Store, Trap};
+
+    #[cfg(feature = "dwarf")]
+    use {
+        crate::parser::Parser,
+        crate::test_util::wat,
+        crate::Module,
+        alloc::vec::Vec,
+        gimli::write::{Address, DwarfUnit, EndianVec, LineProgram, LineString, Sections},
+        gimli::{Encoding, Format, LineEncoding, LittleEndian},
+    };
+
+    #[test]
+    fn test_backtrace_cleared() {
+        let module = module(
+            r#"(module
+                (import "env" "handle" (func $handle))
+                (func (export "trap") unreachable)
+                (func (export "ok"))
+                (func (export "nested") (call $handle)))"#,
+        );
+
+        // calls back into the instance and handles the trap
+        let mut imports = Imports::new();
+        let handle = Extern::typed_func(|mut ctx: FuncContext<'_>, _: ()| {
+            let instance = ctx.module();
+            assert!(instance.exported_func::<(), ()>(ctx.store(), "trap")?.call(ctx.store_mut(), ()).is_err());
+            instance.exported_func::<(), ()>(ctx.store(), "ok")?.call(ctx.store_mut(), ())
+        });
+        imports.define("env", "handle", handle).unwrap();
+
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, Some(imports)).unwrap();
+        let call =
+            |store: &mut Store, name: &str| instance.exported_func::<(), ()>(store, name).unwrap().call(store, ());
+
+        assert!(matches!(call(&mut store, "trap"), Err(Error::Trap(Trap::Unreachable))));
+        assert_eq!(store.last_backtrace().unwrap().frames().len(), 1);
+        call(&mut store, "ok").unwrap();
+        assert!(store.last_backtrace().is_none());
+
+        // nested calls don't clear the backtrace of the trap handled by the host function
+        call(&mut store, "nested").unwrap();
+        assert_eq!(store.last_backtrace().unwrap().frames().len(), 1);
+        call(&mut store, "ok").unwrap();
+        assert!(store.last_backtrace().is_none());
+    }
+
+    #[cfg(feature = "dwarf")]
+    fn leb(out: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    #[cfg(feature = "dwarf")]
+    fn push_custom_section(wasm: &mut Vec<u8>, name: &str, data: &[u8]) {
+        let mut payload = Vec::new();
+        leb(&mut payload, name.len());
+        payload.extend_from_slice(name.as_bytes());
+        payload.extend_from_slice(data);
+        wasm.push(0);
+        leb(wasm, payload.len());
+        wasm.extend(payload);
+    }
+
+    // a line program for `src/lib.rs` with (code section offset, line) rows
+    #[cfg(feature = "dwarf")]
+    fn push_debug_sections(wasm: &mut Vec<u8>, rows: &[(u64, u64)], end: u64) {
+        let encoding = Encoding { format: Format::Dwarf32, version: 4, address_size: 4 };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let mut program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(b"/guest".to_vec()),
+            LineString::String(b"src/lib.rs".to_vec()),
+            None,
+        );
+        let dir = program.default_directory();
+        let file = program.add_file(LineString::String(b"src/lib.rs".to_vec()), dir, None);
+
+        program.begin_sequence(Some(Address::Constant(0)));
+        for &(address, line) in rows {
+            let row = program.row();
+            row.address_offset = address;
+            row.file = file;
+            row.line = line;
+            program.generate_row();
+        }
+        program.end_sequence(end);
+        dwarf.unit.line_program = program;
+
+        let mut sections = Sections::new(EndianVec::new(LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        sections
+            .for_each(|id, data| {
+                if !data.slice().is_empty() {
+                    push_custom_section(wasm, id.name(), data.slice());
+                }
+                Ok::<_, gimli::write::Error>(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "dwarf")]
+    fn test_trap_source_location() {
+        let mut wasm = wat(r#"(module (func (export "f") nop unreachable))"#);
+
+        // code section payload: [count] [size] [locals] [nop] [unreachable] [end]
+        push_debug_sections(&mut wasm, &[(0, 1), (3, 10), (4, 42)], 6);
+
+        let module: Module = Parser::new().with_offsets(true).parse_module_bytes(&wasm).unwrap().into();
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, None).unwrap();
+        let f = instance.exported_func_untyped(&store, "f").unwrap();
+        assert!(matches!(f.call(&mut store, &[]), Err(Error::Trap(Trap::Unreachable))));
+
+        let backtrace = store.last_backtrace().expect("backtrace");
+        let location = backtrace.frames()[0].location.as_ref().expect("source location");
+        assert!(location.file.ends_with("src/lib.rs"), "{}", location.file);
+        assert_eq!(location.line, 42);
+    }
+}
@@ -0,0 +1,339 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::{boxed::Box, format, vec::Vec};
+use core::fmt::Write;
+use tinywasm_types::{FuncAddr, ImportKind, Instruction, ModuleInstanceAddr};
+
+use crate::Module;
+
+/// Records executed instructions and branch outcomes of every function
+#[derive(Debug, Default)]
+pub(crate) struct CoverageRecorder {
+    funcs: Vec<Option<FunctionCoverage>>,
+}
+
+impl CoverageRecorder {
+    // get the coverage of a function, creating it if it doesn't exist yet
+    #[inline]
+    pub(crate) fn function(&mut self, addr: FuncAddr, instructions: &[Instruction]) -> &mut FunctionCoverage {
+        let addr = addr as usize;
+        if addr >= self.funcs.len() {
+            self.funcs.resize_with(addr + 1, || None);
+        }
+        self.funcs[addr].get_or_insert_with(|| FunctionCoverage::new(instructions))
+    }
+
+    // group the recorded functions by module instance, skipping functions of reclaimed instances
+    pub(crate) fn finish(self, mut resolve: impl FnMut(FuncAddr) -> (ModuleInstanceAddr, Option<u32>)) -> Coverage {
+        let mut modules = BTreeMap::<ModuleInstanceAddr, ModuleCoverage>::new();
+        for (addr, func) in self.funcs.into_iter().enumerate() {
+            let Some(func) = func else { continue };
+            let (module_addr, Some(func_idx)) = resolve(addr as FuncAddr) else { continue };
+            modules.entry(module_addr).or_default().functions.insert(func_idx, func);
+        }
+        Coverage { modules }
+    }
+}
+
+/// A fixed size set of bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageBitmap {
+    len: usize,
+    words: Box<[u64]>,
+}
+
+impl CoverageBitmap {
+    /// Create a new bitmap with `len` unset bits
+    pub fn new(len: usize) -> Self {
+        Self { len, words: alloc::vec![0; len.div_ceil(64)].into_boxed_slice() }
+    }
+
+    /// Create a bitmap from its raw words, as returned by [`CoverageBitmap::words`]
+    pub fn from_words(len: usize, words: Box<[u64]>) -> Option<Self> {
+        (words.len() == len.div_ceil(64)).then_some(Self { len, words })
+    }
+
+    /// The number of bits in the bitmap
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the bitmap has no bits
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check if a bit is set
+    pub fn get(&self, idx: usize) -> bool {
+        idx < self.len && self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    /// Set a bit
+    #[inline]
+    pub fn set(&mut self, idx: usize) {
+        if idx < self.len {
+            self.words[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    /// The number of set bits
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// The raw words of the bitmap, with bit `i` stored in `words[i / 64] & (1 << (i % 64))`
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Set all bits that are set in `other`
+    pub fn union(&mut self, other: &Self) {
+        self.words.iter_mut().zip(other.words.iter()).for_each(|(a, b)| *a |= b);
+    }
+}
+
+/// Coverage of a single WebAssembly function
+///
+/// Branch outcomes are indexed as follows:
+/// - `if`: `0` if the `then` branch was taken, `1` otherwise
+/// - `br_if`: `0` if the branch was taken, `1` if execution fell through
+/// - `br_table`: the index of the taken label, with the default label last
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCoverage {
+    instructions: CoverageBitmap,
+    branches: BTreeMap<u32, CoverageBitmap>,
+}
+
+impl FunctionCoverage {
+    /// Create an empty coverage for a function
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let branches = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| Some((i as u32, CoverageBitmap::new(branch_outcomes(instr)?))))
+            .collect();
+        Self { instructions: CoverageBitmap::new(instructions.len()), branches }
+    }
+
+    /// The executed instructions, indexed by instruction index
+    pub fn instructions(&self) -> &CoverageBitmap {
+        &self.instructions
+    }
+
+    /// The taken branch outcomes, indexed by the instruction index of the branch
+    pub fn branches(&self) -> &BTreeMap<u32, CoverageBitmap> {
+        &self.branches
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, instr_idx: usize) {
+        self.instructions.set(instr_idx);
+    }
+
+    #[inline]
+    pub(crate) fn record_branch(&mut self, instr_idx: usize, outcome: usize) {
+        if let Some(branch) = self.branches.get_mut(&(instr_idx as u32)) {
+            branch.set(outcome);
+        }
+    }
+
+    /// Merge the coverage of another run of the same function
+    pub fn merge(&mut self, other: &Self) {
+        self.instructions.union(&other.instructions);
+        for (instr, outcomes) in &other.branches {
+            match self.branches.get_mut(instr) {
+                Some(branch) => branch.union(outcomes),
+                None => {
+                    self.branches.insert(*instr, outcomes.clone());
+                }
+            }
+        }
+    }
+}
+
+// the number of possible outcomes of a branch instruction
+fn branch_outcomes(instr: &Instruction) -> Option<usize> {
+    match instr {
+        Instruction::If(..) | Instruction::IfWithType(..) | Instruction::IfWithFuncType(..) => Some(2),
+        Instruction::BrIf(_) => Some(2),
+        Instruction::BrTable(_, len) => Some(*len as usize + 1),
+        _ => None,
+    }
+}
+
+/// Code coverage recorded by a [`crate::Store`]
+///
+/// See [`crate::Store::start_coverage`]
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    modules: BTreeMap<ModuleInstanceAddr, ModuleCoverage>,
+}
+
+impl Coverage {
+    /// Get the coverage of a module instance
+    pub fn module(&self, addr: ModuleInstanceAddr) -> Option<&ModuleCoverage> {
+        self.modules.get(&addr)
+    }
+
+    /// Iterate over the coverage of all module instances
+    pub fn modules(&self) -> impl Iterator<Item = (ModuleInstanceAddr, &ModuleCoverage)> {
+        self.modules.iter().map(|(addr, module)| (*addr, module))
+    }
+}
+
+/// Code coverage of a module, indexed by function index
+///
+/// Coverage of different instances or runs of the same module can be combined using [`ModuleCoverage::merge`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleCoverage {
+    functions: BTreeMap<u32, FunctionCoverage>,
+}
+
+impl ModuleCoverage {
+    /// Get the coverage of a function. Functions that were never called are not included.
+    pub fn function(&self, func_idx: u32) -> Option<&FunctionCoverage> {
+        self.functions.get(&func_idx)
+    }
+
+    /// Iterate over the coverage of all called functions
+    pub fn functions(&self) -> impl Iterator<Item = (u32, &FunctionCoverage)> {
+        self.functions.iter().map(|(idx, func)| (*idx, func))
+    }
+
+    /// Merge the coverage of another instance or run of the same module
+    pub fn merge(&mut self, other: &Self) {
+        for (idx, func) in &other.functions {
+            match self.functions.get_mut(idx) {
+                Some(f) => f.merge(func),
+                None => {
+                    self.functions.insert(*idx, func.clone());
+                }
+            }
+        }
+    }
+
+    /// Render the coverage as an LCOV tracefile
+    ///
+    /// Requires the module to contain DWARF line information and to be parsed with the `dwarf` feature,
+    /// returns `None` otherwise.
+    pub fn to_lcov(&self, module: &Module) -> Option<String> {
+        let module = &module.0;
+        let line_table = module.line_table.as_ref()?;
+        let imported_funcs =
+            module.imports.iter().filter(|import| matches!(import.kind, ImportKind::Function(_))).count() as u32;
+
+        // line hits and branches per source file
+        let mut files = BTreeMap::<Box<str>, LcovFile>::new();
+        for (i, func) in module.funcs.iter().enumerate() {
+            let func_idx = imported_funcs + i as u32;
+            let coverage = self.functions.get(&func_idx);
+            let name = module.func_name(func_idx).map_or_else(|| format!("func[{func_idx}]"), Into::into);
+            let mut first_line = true;
+
+            for (instr_idx, offset) in func.offsets.iter().enumerate() {
+                let Some(location) = line_table.lookup(*offset) else { continue };
+                let file = files.entry(location.file).or_default();
+                let executed = coverage.is_some_and(|c| c.instructions.get(instr_idx));
+
+                if first_line {
+                    first_line = false;
+                    let called = coverage.is_some_and(|c| c.instructions.get(0));
+                    file.functions.push((location.line, name.clone(), called));
+                }
+
+                let hit = file.lines.entry(location.line).or_default();
+                *hit |= executed;
+
+                let Some(outcomes) = branch_outcomes(&func.instructions[instr_idx]) else { continue };
+                let taken = coverage.and_then(|c| c.branches.get(&(instr_idx as u32)));
+                for outcome in 0..outcomes {
+                    // `-` marks branches that were never evaluated
+                    let taken = match taken {
+                        Some(taken) if executed => Some(taken.get(outcome)),
+                        _ => None,
+                    };
+                    file.branches.push((location.line, func_idx, instr_idx as u32, outcome, taken));
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for (path, file) in files {
+            let _ = writeln!(out, "TN:\nSF:{path}");
+            for (line, name, _) in &file.functions {
+                let _ = writeln!(out, "FN:{line},{name}");
+            }
+            for (_, name, called) in &file.functions {
+                let _ = writeln!(out, "FNDA:{},{name}", *called as u8);
+            }
+            let called = file.functions.iter().filter(|(_, _, called)| *called).count();
+            let _ = writeln!(out, "FNF:{}\nFNH:{called}", file.functions.len());
+
+            for (line, func_idx, instr_idx, outcome, taken) in &file.branches {
+                let block = (*func_idx as u64) << 32 | *instr_idx as u64;
+                let _ = match taken {
+                    Some(taken) => writeln!(out, "BRDA:{line},{block},{outcome},{}", *taken as u8),
+                    None => writeln!(out, "BRDA:{line},{block},{outcome},-"),
+                };
+            }
+            let taken = file.branches.iter().filter(|branch| branch.4 == Some(true)).count();
+            let _ = writeln!(out, "BRF:{}\nBRH:{taken}", file.branches.len());
+
+            for (line, hit) in &file.lines {
+                let _ = writeln!(out, "DA:{line},{}", *hit as u8);
+            }
+            let hit = file.lines.values().filter(|hit| **hit).count();
+            let _ = writeln!(out, "LF:{}\nLH:{hit}\nend_of_record", file.lines.len());
+        }
+
+        Some(out)
+    }
+}
+
+#[derive(Default)]
+struct LcovFile {
+    functions: Vec<(u32, String, bool)>,
+    lines: BTreeMap<u32, bool>,
+    branches: Vec<(u32, u32, u32, usize, Option<bool>)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let instructions = [Instruction::BrIf(0), Instruction::Nop, Instruction::Return];
+        let mut a = FunctionCoverage::new(&instructions);
+        a.record(0);
+        a.record_branch(0, 0);
+
+        let mut b = FunctionCoverage::new(&instructions);
+        b.record(0);
+        b.record_branch(0, 1);
+        b.record(1);
+
+        a.merge(&b);
+        assert_eq!(a.instructions().count_ones(), 2);
+        assert!(!a.instructions().get(2));
+        assert_eq!(a.branches()[&0].words(), &[0b11]);
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_reclaimed_instances_are_skipped() {
+        let module = crate::test_util::module(r#"(module (func (export "f")))"#);
+        let mut store = crate::Store::default();
+        let a = module.clone().instantiate(&mut store, None).unwrap();
+        let b = module.instantiate(&mut store, None).unwrap();
+
+        store.start_coverage();
+        a.exported_func_untyped(&store, "f").unwrap().call(&mut store, &[]).unwrap();
+        b.exported_func_untyped(&store, "f").unwrap().call(&mut store, &[]).unwrap();
+        store.remove_instance(a.id()).unwrap();
+
+        let coverage = store.stop_coverage().unwrap();
+        assert!(coverage.module(a.id()).is_none());
+        assert_eq!(coverage.module(b.id()).unwrap().functions().count(), 1);
+    }
+}
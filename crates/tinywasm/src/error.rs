@@ -1,4 +1,6 @@
+use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::{fmt::Display, ops::ControlFlow};
 use tinywasm_types::FuncType;
 
@@ -9,6 +11,9 @@ pub use tinywasm_parser::ParseError;
 #[derive(Debug)]
 pub enum Error {
     /// A WebAssembly trap occurred
+    ///
+    /// The call stack and source location of the trap are not part of the error,
+    /// see [`crate::Store::last_backtrace`].
     Trap(Trap),
 
     /// A linking error occurred
@@ -29,6 +34,14 @@ pub enum Error {
     /// The store is not the one that the module instance was instantiated in
     InvalidStore,
 
+    /// The module instance was removed from the store
+    InstanceRemoved,
+
+    /// A host function failed with a custom error, see [`Error::host`]
+    ///
+    /// The error is returned unchanged from the call into WebAssembly and can be downcast using [`Error::downcast_ref`].
+    Host(Box<dyn core::error::Error + Send + Sync>),
+
     #[cfg(feature = "std")]
     /// An I/O error occurred
     Io(crate::std::io::Error),
@@ -56,6 +69,26 @@ pub enum LinkingError {
         /// The import name
         name: String,
     },
+
+    /// Multiple imports could not be resolved
+    UnknownImports {
+        /// The module and import names of all unresolved imports
+        imports: Vec<(String, String)>,
+    },
+
+    /// A definition with the same name already exists and shadowing is disabled
+    DuplicateDefinition {
+        /// The module name
+        module: String,
+        /// The definition name, empty for instances and modules
+        name: String,
+    },
+
+    /// A module depends on itself, directly or through other modules
+    CyclicDependency {
+        /// The name of the module
+        module: String,
+    },
 }
 
 impl LinkingError {
@@ -127,6 +160,14 @@ pub enum Trap {
         /// The actual type
         actual: FuncType,
     },
+
+    /// The guest exited, e.g. through a `proc_exit` host function
+    ///
+    /// Host functions can return this to stop execution, see [`Error::exit_code`].
+    Exit {
+        /// The exit code
+        code: i32,
+    },
 }
 
 impl Trap {
@@ -143,6 +184,7 @@ impl Trap {
             Self::UndefinedElement { .. } => "undefined element",
             Self::UninitializedElement { .. } => "uninitialized element",
             Self::IndirectCallTypeMismatch { .. } => "indirect call type mismatch",
+            Self::Exit { .. } => "exit",
         }
     }
 }
@@ -153,6 +195,40 @@ impl LinkingError {
         match self {
             Self::UnknownImport { .. } => "unknown import",
             Self::IncompatibleImportType { .. } => "incompatible import type",
+            Self::UnknownImports { .. } => "unknown imports",
+            Self::DuplicateDefinition { .. } => "duplicate definition",
+            Self::CyclicDependency { .. } => "cyclic dependency",
+        }
+    }
+}
+
+impl Error {
+    /// Create an error from a custom error type, to be returned from host functions
+    pub fn host(err: impl core::error::Error + Send + Sync + 'static) -> Self {
+        Self::Host(Box::new(err))
+    }
+
+    /// Get a reference to the custom error of a host function, if it is of type `T`
+    pub fn downcast_ref<T: core::error::Error + 'static>(&self) -> Option<&T> {
+        match self {
+            Self::Host(err) => err.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Take the custom error of a host function, if it is of type `T`, or return the error unchanged
+    pub fn downcast<T: core::error::Error + 'static>(self) -> Result<T, Self> {
+        match self {
+            Self::Host(err) => err.downcast().map(|err| *err).map_err(Self::Host),
+            err => Err(err),
+        }
+    }
+
+    /// Get the exit code if the guest exited using [`Trap::Exit`]
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Trap(Trap::Exit { code }) => Some(*code),
+            _ => None,
         }
     }
 }
@@ -185,6 +261,8 @@ impl Display for Error {
             Self::UnsupportedFeature(feature) => write!(f, "unsupported feature: {feature}"),
             Self::FuncDidNotReturn => write!(f, "function did not return"),
             Self::InvalidStore => write!(f, "invalid store"),
+            Self::InstanceRemoved => write!(f, "module instance was removed from the store"),
+            Self::Host(err) => write!(f, "host error: {err}"),
         }
     }
 }
@@ -196,6 +274,15 @@ impl Display for LinkingError {
             Self::IncompatibleImportType { module, name } => {
                 write!(f, "incompatible import type: {}.{}", module, name)
             }
+            Self::UnknownImports { imports } => {
+                write!(f, "unknown imports:")?;
+                imports.iter().try_for_each(|(module, name)| write!(f, " {}.{}", module, name))
+            }
+            Self::DuplicateDefinition { module, name } if name.is_empty() => {
+                write!(f, "duplicate definition: {}", module)
+            }
+            Self::DuplicateDefinition { module, name } => write!(f, "duplicate definition: {}.{}", module, name),
+            Self::CyclicDependency { module } => write!(f, "cyclic dependency: {}", module),
         }
     }
 }
@@ -221,11 +308,19 @@ impl Display for Trap {
             Self::IndirectCallTypeMismatch { expected, actual } => {
                 write!(f, "indirect call type mismatch: expected={expected:?}, actual={actual:?}")
             }
+            Self::Exit { code } => write!(f, "exit: code={code}"),
         }
     }
 }
 
-impl core::error::Error for Error {}
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Host(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 #[cfg(feature = "parser")]
 impl From<tinywasm_parser::ParseError> for Error {
@@ -249,3 +344,82 @@ impl<T> Controlify<T> for Result<T, Error> {
         }
     }
 }
+
+#[cfg(all(test, feature = "parser"))]
+mod error_tests {
+    use super::*;
+    use crate::{Extern, FuncContext, Imports, ModuleInstance, Store};
+
+    #[derive(Debug, PartialEq)]
+    struct MyErr(u32);
+
+    impl Display for MyErr {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "my error {}", self.0)
+        }
+    }
+
+    impl core::error::Error for MyErr {}
+
+    // `run` calls the `enter` host function, which calls back into `inner`, which calls `fail`
+    fn instantiate(store: &mut Store, fail: Extern) -> ModuleInstance {
+        let module = crate::test_util::module(
+            r#"(module
+                (import "env" "enter" (func $enter (param i32)))
+                (import "env" "fail" (func $fail (param i32)))
+                (global $after (export "after") (mut i32) (i32.const 0))
+                (func (export "inner") (param i32) (call $fail (local.get 0)) (global.set $after (i32.const 1)))
+                (func (export "run") (param i32) (call $enter (local.get 0)) (global.set $after (i32.const 2))))"#,
+        );
+
+        let mut imports = Imports::new();
+        let enter = Extern::typed_func(|mut ctx: FuncContext<'_>, n: i32| {
+            let inner = ctx.module().exported_func::<i32, ()>(ctx.store(), "inner")?;
+            inner.call(ctx.store_mut(), n)
+        });
+        imports.define("env", "enter", enter).unwrap().define("env", "fail", fail).unwrap();
+        module.instantiate(store, Some(imports)).unwrap()
+    }
+
+    fn after(store: &mut Store, instance: &ModuleInstance) -> i32 {
+        instance.exported_global(store, "after").unwrap().get().unwrap()
+    }
+
+    #[test]
+    fn test_host_error() {
+        let mut store = Store::default();
+        let fail = Extern::typed_func(|_: FuncContext<'_>, n: i32| Err::<(), _>(Error::host(MyErr(n as u32))));
+        let instance = instantiate(&mut store, fail);
+
+        let run = instance.exported_func_untyped(&store, "run").unwrap();
+        let err = run.call(&mut store, &[5.into()]).unwrap_err();
+        assert_eq!(err.downcast_ref::<MyErr>(), Some(&MyErr(5)));
+        assert_eq!(err.to_string(), "host error: my error 5");
+        assert_eq!(err.downcast::<MyErr>().unwrap(), MyErr(5));
+        assert_eq!(after(&mut store, &instance), 0);
+
+        // other errors can't be downcast
+        let err = Error::Trap(Trap::Unreachable);
+        assert!(err.downcast_ref::<MyErr>().is_none());
+        assert!(matches!(err.downcast::<MyErr>(), Err(Error::Trap(Trap::Unreachable))));
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut store = Store::default();
+        let fail = Extern::typed_func(|_: FuncContext<'_>, code: i32| Err::<(), _>(Trap::Exit { code }.into()));
+        let instance = instantiate(&mut store, fail);
+
+        // execution stops in both instances of the nested call
+        let run = instance.exported_func::<i32, ()>(&store, "run").unwrap();
+        let err = run.call(&mut store, 3).unwrap_err();
+        assert!(matches!(err, Error::Trap(Trap::Exit { code: 3 })));
+        assert_eq!(err.exit_code(), Some(3));
+        assert_eq!(after(&mut store, &instance), 0);
+        assert_eq!(Error::Trap(Trap::Unreachable).exit_code(), None);
+
+        // the store can still be used afterwards
+        let inner = instance.exported_func::<i32, ()>(&store, "inner").unwrap();
+        assert_eq!(inner.call(&mut store, 0).unwrap_err().exit_code(), Some(0));
+    }
+}
@@ -1,12 +1,16 @@
 use crate::interpreter::stack::{CallFrame, Stack};
-use crate::{log, unlikely, Function};
-use crate::{Error, FuncContext, Result, Store};
-use alloc::{boxed::Box, format, string::String, string::ToString, vec, vec::Vec};
-use tinywasm_types::{FuncType, ModuleInstanceAddr, ValType, WasmValue};
-
-#[derive(Debug)]
+use crate::stats::StatsRecorder;
+use crate::store::HOST_OWNER;
+use crate::{log, unlikely, Function, MaybeSendSync};
+use crate::{CallStats, Error, ExternRef, FuncContext, IntoHostFunc, Result, Store};
+use alloc::{boxed::Box, format, string::String, string::ToString, vec::Vec};
+use core::fmt::Debug;
+use tinywasm_types::{FuncAddr, FuncType, ModuleInstanceAddr, ValType, WasmValue};
+
+#[derive(Debug, Clone)]
 /// A function handle
 pub struct FuncHandle {
+    pub(crate) store_id: usize,
     pub(crate) module_addr: ModuleInstanceAddr,
     pub(crate) addr: u32,
     pub(crate) ty: FuncType,
@@ -16,11 +20,68 @@ pub struct FuncHandle {
 }
 
 impl FuncHandle {
+    /// Create a host function in the store
+    ///
+    /// Unlike host functions defined as imports, the function can be stored in tables or passed to WebAssembly
+    /// functions as a `RefFunc` value, see [`FuncHandle::func_ref`].
+    pub fn new(
+        store: &mut Store,
+        ty: &FuncType,
+        func: impl Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + MaybeSendSync + 'static,
+    ) -> Result<Self> {
+        Self::from_function(store, Function::host(ty, func))
+    }
+
+    /// Create a host function from a Rust closure in the store, see [`IntoHostFunc`]
+    ///
+    /// See [`FuncHandle::new`]
+    pub fn wrap<P, R>(store: &mut Store, func: impl IntoHostFunc<P, R>) -> Result<Self> {
+        Self::from_function(store, func.into_host_func())
+    }
+
+    fn from_function(store: &mut Store, func: Function) -> Result<Self> {
+        let ty = func.ty().clone();
+        let addr = store.add_func(func, HOST_OWNER)?;
+        Ok(Self { store_id: store.id(), module_addr: HOST_OWNER, addr, ty, name: None })
+    }
+
+    /// Get a `RefFunc` value referencing the function, which can be stored in tables or passed to functions
+    ///
+    /// The value is only meaningful in the function's store, see [`FuncRef::new`] for a checked reference.
+    pub fn func_ref(&self) -> WasmValue {
+        WasmValue::RefFunc(self.addr)
+    }
+
+    /// Get the function's type
+    pub fn ty(&self) -> &FuncType {
+        &self.ty
+    }
+
+    /// Convert the handle into a typed function handle
+    ///
+    /// Parameter and result types are checked when the function is called.
+    pub fn typed<P, R>(self) -> FuncHandleTyped<P, R> {
+        FuncHandleTyped { func: self, marker: core::marker::PhantomData }
+    }
+
     /// Call a function (Invocation)
     ///
     /// See <https://webassembly.github.io/spec/core/exec/modules.html#invocation>
     #[inline]
     pub fn call(&self, store: &mut Store, params: &[WasmValue]) -> Result<Vec<WasmValue>> {
+        // only errors of the current call are reported, nested calls from host functions keep the backtrace
+        if store.call_depth == 0 {
+            store.last_backtrace = None;
+        }
+
+        store.call_depth += 1;
+        let res = self.call_inner(store, params);
+        store.call_depth -= 1;
+        res
+    }
+
+    #[inline]
+    fn call_inner(&self, store: &mut Store, params: &[WasmValue]) -> Result<Vec<WasmValue>> {
         // Comments are ordered by the steps in the spec
         // In this implementation, some steps are combined and ordered differently for performance reasons
 
@@ -48,18 +109,36 @@ impl FuncHandle {
             return Err(Error::Other("Type mismatch".into()));
         }
 
+        if unlikely(store.id() != self.store_id) {
+            return Err(Error::InvalidStore);
+        }
+
+        if unlikely(store.is_removed(self.module_addr)) {
+            return Err(Error::InstanceRemoved);
+        }
+
+        // function references are addresses in the store, so they have to point to an existing function
+        let funcs = store.data.funcs.len();
+        if unlikely(params.iter().any(|p| matches!(p, WasmValue::RefFunc(addr) if *addr as usize >= funcs))) {
+            return Err(Error::Other("invalid function reference".into()));
+        }
+
         let func_inst = store.get_func(self.addr);
         let wasm_func = match &func_inst.func {
             Function::Host(host_func) => {
-                let func = &host_func.clone().func;
+                let func = host_func.clone();
+                if let Some(stats) = &mut store.call_stats {
+                    stats.record_host_call();
+                }
+
                 let ctx = FuncContext { store, module_addr: self.module_addr };
-                return (func)(ctx, params);
+                return func.call(ctx, params);
             }
             Function::Wasm(wasm_func) => wasm_func,
         };
 
         // 6. Let f be the dummy frame
-        let call_frame = CallFrame::new(wasm_func.clone(), func_inst.owner, params, 0);
+        let call_frame = CallFrame::new(wasm_func.clone(), self.addr, func_inst.owner, params, 0);
 
         // 7. Push the frame f to the call stack
         // & 8. Push the values to the stack (Not needed since the call frame owns the values)
@@ -76,11 +155,32 @@ impl FuncHandle {
         // assert!(stack.values.len() >= result_m);
 
         // 2. Pop m values from the stack
-        let res = stack.values.pop_results(&func_ty.results);
+        let res = stack.values.pop_values(&func_ty.results);
 
         // The values are returned as the results of the invocation.
         Ok(res)
     }
+
+    /// Call a function and collect execution statistics
+    ///
+    /// Statistics of nested calls made by host functions are included.
+    /// Collecting statistics slows down execution, so this should only be used for diagnostics.
+    pub fn call_with_stats(&self, store: &mut Store, params: &[WasmValue]) -> Result<(Vec<WasmValue>, CallStats)> {
+        let outer = store.call_stats.replace(StatsRecorder::default());
+        let res = self.call(store, params);
+        let recorder = core::mem::replace(&mut store.call_stats, outer).unwrap_or_default();
+
+        // add the statistics to the outer call, if any
+        if let Some(outer) = &mut store.call_stats {
+            outer.merge(&recorder);
+        }
+
+        let stats = recorder.finish(|addr| match &store.get_func(addr).func {
+            Function::Wasm(wasm_func) => &wasm_func.instructions,
+            Function::Host(_) => &[],
+        });
+        Ok((res?, stats))
+    }
 }
 
 #[derive(Debug)]
@@ -91,14 +191,25 @@ pub struct FuncHandleTyped<P, R> {
     pub(crate) marker: core::marker::PhantomData<(P, R)>,
 }
 
-pub trait IntoWasmValueTuple {
-    fn into_wasm_value_tuple(self) -> Vec<WasmValue>;
+pub trait IntoWasmValueTuple: Sized {
+    // pass the values to `push` in order, without allocating
+    fn write_wasm_values(self, push: impl FnMut(WasmValue));
+
+    fn into_wasm_value_tuple(self) -> Vec<WasmValue> {
+        let mut values = Vec::new();
+        self.write_wasm_values(|value| values.push(value));
+        values
+    }
 }
 
-pub trait FromWasmValueTuple {
-    fn from_wasm_value_tuple(values: &[WasmValue]) -> Result<Self>
-    where
-        Self: Sized;
+pub trait FromWasmValueTuple: Sized {
+    // read the values in order from `next`, which is given the type of the next value
+    fn from_wasm_values(next: impl FnMut(ValType) -> Option<WasmValue>) -> Result<Self>;
+
+    fn from_wasm_value_tuple(values: &[WasmValue]) -> Result<Self> {
+        let mut values = values.iter();
+        Self::from_wasm_values(|_| values.next().copied())
+    }
 }
 
 impl<P: IntoWasmValueTuple, R: FromWasmValueTuple> FuncHandleTyped<P, R> {
@@ -113,169 +224,258 @@ impl<P: IntoWasmValueTuple, R: FromWasmValueTuple> FuncHandleTyped<P, R> {
         // Convert the Vec<WasmValue> back to R
         R::from_wasm_value_tuple(&result)
     }
-}
 
-macro_rules! impl_into_wasm_value_tuple {
-    ($($T:ident),*) => {
-        impl<$($T),*> IntoWasmValueTuple for ($($T,)*)
-        where
-            $($T: Into<WasmValue>),*
-        {
-            #[allow(non_snake_case)]
-            #[inline]
-            fn into_wasm_value_tuple(self) -> Vec<WasmValue> {
-                let ($($T,)*) = self;
-                vec![$($T.into(),)*]
-            }
-        }
+    /// Call a typed function and collect execution statistics
+    ///
+    /// See [`FuncHandle::call_with_stats`]
+    pub fn call_with_stats(&self, store: &mut Store, params: P) -> Result<(R, CallStats)> {
+        let wasm_values = params.into_wasm_value_tuple();
+        let (result, stats) = self.func.call_with_stats(store, &wasm_values)?;
+        Ok((R::from_wasm_value_tuple(&result)?, stats))
     }
 }
 
-macro_rules! impl_into_wasm_value_tuple_single {
-    ($T:ident) => {
-        impl IntoWasmValueTuple for $T {
-            #[inline]
-            fn into_wasm_value_tuple(self) -> Vec<WasmValue> {
-                vec![self.into()]
+/// A Rust type that represents a single WebAssembly value in typed functions
+///
+/// Implemented for `i32`, `u32`, `i64`, `u64`, `f32`, `f64`, `u128` (`v128`), [`FuncRef`] and [`ExternRef`].
+/// Nullable references are represented as `Option<FuncRef>` and `Option<ExternRef>`.
+pub trait WasmType: Sized {
+    /// The WebAssembly type of the value
+    fn val_type() -> ValType;
+
+    /// Convert the value into a [`WasmValue`]
+    fn into_wasm_value(self) -> WasmValue;
+
+    /// Convert a [`WasmValue`] into this type, returning `None` if the types don't match
+    fn from_wasm_value(value: WasmValue) -> Option<Self>;
+}
+
+macro_rules! impl_wasm_type {
+    ($($t:ty => $variant:ident as $inner:ty),*) => {
+        $(
+            impl WasmType for $t {
+                #[inline]
+                fn val_type() -> ValType {
+                    ValType::$variant
+                }
+
+                #[inline]
+                fn into_wasm_value(self) -> WasmValue {
+                    WasmValue::$variant(self as $inner)
+                }
+
+                #[inline]
+                fn from_wasm_value(value: WasmValue) -> Option<Self> {
+                    match value {
+                        WasmValue::$variant(value) => Some(value as $t),
+                        _ => None,
+                    }
+                }
             }
-        }
+        )*
     };
 }
 
-macro_rules! impl_from_wasm_value_tuple {
-    ($($T:ident),*) => {
-        impl<$($T),*> FromWasmValueTuple for ($($T,)*)
-        where
-            $($T: TryFrom<WasmValue, Error = ()>),*
-        {
-            #[inline]
-            fn from_wasm_value_tuple(values: &[WasmValue]) -> Result<Self> {
-                #[allow(unused_variables, unused_mut)]
-                let mut iter = values.iter();
-
-                Ok((
-                    $(
-                        $T::try_from(
-                            *iter.next()
-                            .ok_or(Error::Other("Not enough values in WasmValue vector".to_string()))?
-                        )
-                        .map_err(|e| Error::Other(format!("FromWasmValueTuple: Could not convert WasmValue to expected type: {:?}", e,
-                    )))?,
-                    )*
-                ))
+impl_wasm_type! { i32 => I32 as i32, u32 => I32 as i32, i64 => I64 as i64, u64 => I64 as i64, f32 => F32 as f32, f64 => F64 as f64, u128 => V128 as u128 }
+
+macro_rules! impl_wasm_type_ref {
+    ($($t:ident => $variant:ident),*) => {
+        $(
+            impl WasmType for $t {
+                #[inline]
+                fn val_type() -> ValType {
+                    ValType::$variant
+                }
+
+                #[inline]
+                fn into_wasm_value(self) -> WasmValue {
+                    self.into()
+                }
+
+                #[inline]
+                fn from_wasm_value(value: WasmValue) -> Option<Self> {
+                    value.try_into().ok()
+                }
             }
-        }
-    }
-}
 
-macro_rules! impl_from_wasm_value_tuple_single {
-    ($T:ident) => {
-        impl FromWasmValueTuple for $T {
-            #[inline]
-            fn from_wasm_value_tuple(values: &[WasmValue]) -> Result<Self> {
-                #[allow(unused_variables, unused_mut)]
-                let mut iter = values.iter();
-                $T::try_from(*iter.next().ok_or(Error::Other("Not enough values in WasmValue vector".to_string()))?)
-                    .map_err(|e| {
-                        Error::Other(format!(
-                            "FromWasmValueTupleSingle: Could not convert WasmValue to expected type: {:?}",
-                            e
-                        ))
-                    })
+            impl WasmType for Option<$t> {
+                #[inline]
+                fn val_type() -> ValType {
+                    ValType::$variant
+                }
+
+                #[inline]
+                fn into_wasm_value(self) -> WasmValue {
+                    self.map_or(WasmValue::RefNull(ValType::$variant), Into::into)
+                }
+
+                #[inline]
+                fn from_wasm_value(value: WasmValue) -> Option<Self> {
+                    match value {
+                        WasmValue::RefNull(ValType::$variant) => Some(None),
+                        value => value.try_into().ok().map(Some),
+                    }
+                }
             }
-        }
+        )*
     };
 }
 
-pub trait ValTypesFromTuple {
-    fn val_types() -> Box<[ValType]>;
+impl_wasm_type_ref! { FuncRef => RefFunc, ExternRef => RefExtern }
+
+/// A reference to a function in a store, the typed form of a `WasmValue::RefFunc`
+///
+/// Use [`Store::func_from_ref`] to get a [`FuncHandle`] that can be called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FuncRef(FuncAddr);
+
+impl FuncRef {
+    /// Create a reference to a function in the given store
+    ///
+    /// Returns [`Error::InvalidStore`] if the function belongs to a different store,
+    /// as the reference would point to an unrelated function there.
+    pub fn new(store: &Store, func: &FuncHandle) -> Result<Self> {
+        if func.store_id != store.id() {
+            return Err(Error::InvalidStore);
+        }
+        Ok(Self(func.addr))
+    }
+
+    /// Get the address of the referenced function in the store
+    pub fn addr(&self) -> FuncAddr {
+        self.0
+    }
 }
 
-pub trait ToValType {
-    fn to_val_type() -> ValType;
+impl From<FuncRef> for WasmValue {
+    fn from(value: FuncRef) -> Self {
+        WasmValue::RefFunc(value.0)
+    }
 }
 
-impl ToValType for i32 {
-    fn to_val_type() -> ValType {
-        ValType::I32
+impl TryFrom<WasmValue> for FuncRef {
+    type Error = ();
+
+    fn try_from(value: WasmValue) -> core::result::Result<Self, Self::Error> {
+        match value {
+            WasmValue::RefFunc(addr) => Ok(Self(addr)),
+            _ => Err(()),
+        }
     }
 }
 
-impl ToValType for i64 {
-    fn to_val_type() -> ValType {
-        ValType::I64
+impl<T: WasmType> IntoWasmValueTuple for T {
+    #[inline]
+    fn write_wasm_values(self, mut push: impl FnMut(WasmValue)) {
+        push(self.into_wasm_value())
     }
 }
 
-impl ToValType for f32 {
-    fn to_val_type() -> ValType {
-        ValType::F32
+impl<T: WasmType> FromWasmValueTuple for T {
+    #[inline]
+    fn from_wasm_values(mut next: impl FnMut(ValType) -> Option<WasmValue>) -> Result<Self> {
+        from_wasm_value(
+            next(T::val_type()).ok_or_else(|| Error::Other("Not enough values in WasmValue vector".to_string()))?,
+        )
     }
 }
 
-impl ToValType for f64 {
-    fn to_val_type() -> ValType {
-        ValType::F64
+impl<T: WasmType> ValTypesFromTuple for T {
+    #[inline]
+    fn val_types() -> Box<[ValType]> {
+        Box::new([T::val_type()])
     }
 }
 
-macro_rules! impl_val_types_from_tuple {
-    ($($t:ident),+) => {
-        impl<$($t),+> ValTypesFromTuple for ($($t,)+)
-        where
-            $($t: ToValType,)+
-        {
+#[inline]
+fn from_wasm_value<T: WasmType>(value: WasmValue) -> Result<T> {
+    T::from_wasm_value(value).ok_or_else(|| {
+        Error::Other(format!("FromWasmValueTuple: Could not convert {:?} to {:?}", value, T::val_type()))
+    })
+}
+
+macro_rules! impl_wasm_value_tuple {
+    ($($T:ident),*) => {
+        impl<$($T: WasmType),*> IntoWasmValueTuple for ($($T,)*) {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            #[inline]
+            fn write_wasm_values(self, mut push: impl FnMut(WasmValue)) {
+                let ($($T,)*) = self;
+                $(push($T.into_wasm_value());)*
+            }
+        }
+
+        impl<$($T: WasmType),*> FromWasmValueTuple for ($($T,)*) {
+            #[allow(unused_variables, unused_mut)]
+            #[inline]
+            fn from_wasm_values(mut next: impl FnMut(ValType) -> Option<WasmValue>) -> Result<Self> {
+                Ok((
+                    $(
+                        from_wasm_value::<$T>(
+                            next($T::val_type()).ok_or_else(|| Error::Other("Not enough values in WasmValue vector".to_string()))?
+                        )?,
+                    )*
+                ))
+            }
+        }
+
+        impl<$($T: WasmType),*> ValTypesFromTuple for ($($T,)*) {
             #[inline]
             fn val_types() -> Box<[ValType]> {
-                Box::new([$($t::to_val_type(),)+])
+                Box::new([$($T::val_type(),)*])
             }
         }
-    };
+    }
 }
 
-impl ValTypesFromTuple for () {
-    #[inline]
-    fn val_types() -> Box<[ValType]> {
-        Box::new([])
-    }
+pub trait ValTypesFromTuple {
+    fn val_types() -> Box<[ValType]>;
 }
 
-impl<T: ToValType> ValTypesFromTuple for T {
-    #[inline]
-    fn val_types() -> Box<[ValType]> {
-        Box::new([T::to_val_type()])
+impl_wasm_value_tuple!();
+impl_wasm_value_tuple!(T1);
+impl_wasm_value_tuple!(T1, T2);
+impl_wasm_value_tuple!(T1, T2, T3);
+impl_wasm_value_tuple!(T1, T2, T3, T4);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_wasm_value_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+
+#[cfg(test)]
+mod func_tests {
+    use super::*;
+
+    #[test]
+    fn test_func_refs() {
+        let mut store = Store::default();
+        let one = FuncHandle::wrap(&mut store, || 1).unwrap();
+        let call = FuncHandle::wrap(&mut store, |mut ctx: FuncContext<'_>, f: FuncRef| {
+            let func = ctx.store().func_from_ref(f.into()).ok_or(Error::Other("invalid".into()))?;
+            func.typed::<(), i32>().call(ctx.store_mut(), ())
+        })
+        .unwrap();
+        let call = call.typed::<FuncRef, i32>();
+        let one = FuncRef::new(&store, &one).unwrap();
+        assert_eq!(call.call(&mut store, one).unwrap(), 1);
+
+        // references outside the store are rejected before they reach the function
+        let invalid = FuncRef::try_from(WasmValue::RefFunc(999)).unwrap();
+        assert!(matches!(call.call(&mut store, invalid), Err(Error::Other(e)) if e == "invalid function reference"));
+        assert!(call.func.call(&mut store, &[WasmValue::RefFunc(999)]).is_err());
+
+        // references to functions of other stores can't be created
+        let mut other = Store::default();
+        let two = FuncHandle::wrap(&mut other, || 2).unwrap();
+        assert!(matches!(FuncRef::new(&store, &two), Err(Error::InvalidStore)));
+        assert_eq!(FuncRef::new(&other, &two).unwrap().addr(), 0);
     }
 }
-
-impl_from_wasm_value_tuple_single!(i32);
-impl_from_wasm_value_tuple_single!(i64);
-impl_from_wasm_value_tuple_single!(f32);
-impl_from_wasm_value_tuple_single!(f64);
-
-impl_into_wasm_value_tuple_single!(i32);
-impl_into_wasm_value_tuple_single!(i64);
-impl_into_wasm_value_tuple_single!(f32);
-impl_into_wasm_value_tuple_single!(f64);
-
-impl_val_types_from_tuple!(T1);
-impl_val_types_from_tuple!(T1, T2);
-impl_val_types_from_tuple!(T1, T2, T3);
-impl_val_types_from_tuple!(T1, T2, T3, T4);
-impl_val_types_from_tuple!(T1, T2, T3, T4, T5);
-impl_val_types_from_tuple!(T1, T2, T3, T4, T5, T6);
-
-impl_from_wasm_value_tuple!();
-impl_from_wasm_value_tuple!(T1);
-impl_from_wasm_value_tuple!(T1, T2);
-impl_from_wasm_value_tuple!(T1, T2, T3);
-impl_from_wasm_value_tuple!(T1, T2, T3, T4);
-impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5);
-impl_from_wasm_value_tuple!(T1, T2, T3, T4, T5, T6);
-
-impl_into_wasm_value_tuple!();
-impl_into_wasm_value_tuple!(T1);
-impl_into_wasm_value_tuple!(T1, T2);
-impl_into_wasm_value_tuple!(T1, T2, T3);
-impl_into_wasm_value_tuple!(T1, T2, T3, T4);
-impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5);
-impl_into_wasm_value_tuple!(T1, T2, T3, T4, T5, T6);
@@ -0,0 +1,319 @@
+use alloc::string::ToString;
+use tinywasm_types::{GlobalAddr, GlobalType, MemAddr, MemoryType, TableAddr, TableType, ValType, WasmValue};
+
+use crate::store::{TableElement, HOST_OWNER};
+use crate::{Error, FuncHandle, MemoryRef, MemoryRefMut, Result, Store};
+
+// This module contains handles to memories, tables and globals that are owned by a store
+
+/// A handle to a memory in a store
+///
+/// Created using [`Memory::new`]. Can be defined as an import by converting it into an [`Extern`](crate::Extern),
+/// all instances importing it share the same memory, which stays in the store until the store is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memory {
+    store_id: usize,
+    addr: MemAddr,
+}
+
+impl Memory {
+    /// Create a new memory in the store
+    pub fn new(store: &mut Store, ty: MemoryType) -> Result<Self> {
+        let addr = store.add_mem(ty, HOST_OWNER)?;
+        Ok(Self { store_id: store.id(), addr })
+    }
+
+    /// Get the type of the memory
+    pub fn ty(&self, store: &Store) -> Result<MemoryType> {
+        Ok(store.get_mem(self.addr(store)?).kind)
+    }
+
+    /// Get a reference to the memory
+    pub fn get<'a>(&self, store: &'a Store) -> Result<MemoryRef<'a>> {
+        Ok(MemoryRef(store.get_mem(self.addr(store)?)))
+    }
+
+    /// Get a mutable reference to the memory
+    pub fn get_mut<'a>(&self, store: &'a mut Store) -> Result<MemoryRefMut<'a>> {
+        let addr = self.addr(store)?;
+        Ok(MemoryRefMut(store.get_mem_mut(addr)))
+    }
+
+    pub(crate) fn from_addr(store: &Store, addr: MemAddr) -> Self {
+        Self { store_id: store.id(), addr }
+    }
+
+    pub(crate) fn addr(&self, store: &Store) -> Result<MemAddr> {
+        check_store(self.store_id, store)?;
+        Ok(self.addr)
+    }
+}
+
+/// A handle to a table in a store
+///
+/// Created using [`Table::new`]. Can be defined as an import by converting it into an [`Extern`](crate::Extern),
+/// all instances importing it share the same table, which stays in the store until the store is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Table {
+    store_id: usize,
+    addr: TableAddr,
+}
+
+impl Table {
+    /// Create a new table in the store, with all elements set to `init`
+    pub fn new(store: &mut Store, ty: TableType, init: WasmValue) -> Result<Self> {
+        let init = table_element(store, ty.element_type, init)?;
+        let addr = store.add_table(ty, HOST_OWNER)?;
+        store.get_table_mut(addr).elements.fill(init);
+        Ok(Self { store_id: store.id(), addr })
+    }
+
+    /// Get the type of the table
+    pub fn ty(&self, store: &Store) -> Result<TableType> {
+        Ok(store.get_table(self.addr(store)?).kind.clone())
+    }
+
+    /// Get the current number of elements in the table
+    pub fn size(&self, store: &Store) -> Result<u32> {
+        Ok(store.get_table(self.addr(store)?).size() as u32)
+    }
+
+    /// Get the element at the given index
+    pub fn get(&self, store: &Store, index: u32) -> Result<WasmValue> {
+        store.get_table(self.addr(store)?).get_wasm_val(index)
+    }
+
+    /// Get a handle to the function at the given index, or `None` if the element is a null reference
+    pub fn get_func(&self, store: &Store, index: u32) -> Result<Option<FuncHandle>> {
+        Ok(store.func_from_ref(self.get(store, index)?))
+    }
+
+    /// Set the element at the given index
+    pub fn set(&self, store: &mut Store, index: u32, value: WasmValue) -> Result<()> {
+        let addr = self.addr(store)?;
+        let value = table_element(store, store.get_table(addr).kind.element_type, value)?;
+        store.get_table_mut(addr).set(index, value)
+    }
+
+    /// Grow the table by `delta` elements set to `init`, returning the previous size
+    pub fn grow(&self, store: &mut Store, delta: u32, init: WasmValue) -> Result<u32> {
+        let addr = self.addr(store)?;
+        let init = table_element(store, store.get_table(addr).kind.element_type, init)?;
+        let table = store.get_table_mut(addr);
+        let size = table.size() as u32;
+        table.grow(delta.try_into().map_err(|_| Error::Other("table size overflow".to_string()))?, init)?;
+        Ok(size)
+    }
+
+    /// Set `len` elements starting at `offset` to `value`
+    pub fn fill(&self, store: &mut Store, offset: u32, len: u32, value: WasmValue) -> Result<()> {
+        let addr = self.addr(store)?;
+        let value = table_element(store, store.get_table(addr).kind.element_type, value)?;
+        store.get_table_mut(addr).fill(offset as usize, len as usize, value)
+    }
+
+    pub(crate) fn from_addr(store: &Store, addr: TableAddr) -> Self {
+        Self { store_id: store.id(), addr }
+    }
+
+    pub(crate) fn addr(&self, store: &Store) -> Result<TableAddr> {
+        check_store(self.store_id, store)?;
+        Ok(self.addr)
+    }
+}
+
+/// A handle to a global in a store
+///
+/// Created using [`Global::new`]. Can be defined as an import by converting it into an [`Extern`](crate::Extern),
+/// all instances importing it share the same global, which stays in the store until the store is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Global {
+    store_id: usize,
+    addr: GlobalAddr,
+}
+
+impl Global {
+    /// Create a new global in the store
+    pub fn new(store: &mut Store, ty: GlobalType, value: WasmValue) -> Result<Self> {
+        check_value(store, ty.ty, &value)?;
+        let addr = store.add_global(ty, value.into(), HOST_OWNER)?;
+        Ok(Self { store_id: store.id(), addr })
+    }
+
+    /// Get the type of the global
+    pub fn ty(&self, store: &Store) -> Result<GlobalType> {
+        Ok(store.get_global(self.addr(store)?).ty)
+    }
+
+    /// Get the current value of the global
+    pub fn get(&self, store: &Store) -> Result<WasmValue> {
+        let global = store.get_global(self.addr(store)?);
+        Ok(global.value.get().attach_type(global.ty.ty))
+    }
+
+    /// Set the value of the global, which has to be mutable
+    pub fn set(&self, store: &mut Store, value: WasmValue) -> Result<()> {
+        let global = store.get_global(self.addr(store)?);
+        if !global.ty.mutable {
+            return Err(Error::Other("global is immutable".to_string()));
+        }
+
+        check_value(store, global.ty.ty, &value)?;
+        global.value.set(value.into());
+        Ok(())
+    }
+
+    pub(crate) fn from_addr(store: &Store, addr: GlobalAddr) -> Self {
+        Self { store_id: store.id(), addr }
+    }
+
+    pub(crate) fn addr(&self, store: &Store) -> Result<GlobalAddr> {
+        check_store(self.store_id, store)?;
+        Ok(self.addr)
+    }
+}
+
+fn check_store(store_id: usize, store: &Store) -> Result<()> {
+    match store_id == store.id() {
+        true => Ok(()),
+        false => Err(Error::InvalidStore),
+    }
+}
+
+fn check_type(ty: ValType, value: &WasmValue) -> Result<()> {
+    match value.val_type() == ty {
+        true => Ok(()),
+        false => Err(Error::Other("Type mismatch".to_string())),
+    }
+}
+
+// check the type of a value, function references have to exist in the store
+pub(crate) fn check_value(store: &Store, ty: ValType, value: &WasmValue) -> Result<()> {
+    check_type(ty, value)?;
+    match value {
+        WasmValue::RefFunc(addr) if *addr as usize >= store.data.funcs.len() => {
+            Err(Error::Other("invalid function reference".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// convert a value to a table element, see `check_value`
+pub(crate) fn table_element(store: &Store, ty: ValType, value: WasmValue) -> Result<TableElement> {
+    check_value(store, ty, &value)?;
+    match value {
+        WasmValue::RefFunc(addr) | WasmValue::RefExtern(addr) => Ok(TableElement::Initialized(addr)),
+        _ => Ok(TableElement::Uninitialized),
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod handles_tests {
+    use super::*;
+    use crate::{Extern, Imports};
+    use tinywasm_types::MemoryArch;
+
+    #[test]
+    fn test_shared_imports() {
+        let mut store = Store::default();
+        let memory = Memory::new(&mut store, MemoryType::new(MemoryArch::I32, 1, None, None)).unwrap();
+        let table_ty = TableType::new(ValType::RefFunc, 2, None);
+        let table = Table::new(&mut store, table_ty, WasmValue::RefNull(ValType::RefFunc)).unwrap();
+        let counter =
+            Global::new(&mut store, GlobalType { mutable: true, ty: ValType::I32 }, WasmValue::I32(0)).unwrap();
+
+        let module = crate::test_util::module(
+            r#"(module
+                (import "env" "memory" (memory 1))
+                (import "env" "table" (table 2 funcref))
+                (import "env" "counter" (global $counter (mut i32)))
+                (type $t (func (result i32)))
+                (func $f (result i32) (i32.const 7))
+                (elem declare func $f)
+                (func (export "write")
+                    (i32.store (i32.const 0) (i32.const 42))
+                    (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+                    (table.set (i32.const 1) (ref.func $f)))
+                (func (export "read") (result i32) (i32.add (i32.load (i32.const 0)) (global.get $counter)))
+                (func (export "call") (result i32) (call_indirect (type $t) (i32.const 1))))"#,
+        );
+
+        let mut instances = alloc::vec::Vec::new();
+        for _ in 0..2 {
+            let mut imports = Imports::new();
+            imports.define("env", "memory", Extern::from(memory)).unwrap();
+            imports.define("env", "table", Extern::from(table)).unwrap();
+            imports.define("env", "counter", Extern::from(counter)).unwrap();
+            instances.push(module.clone().instantiate(&mut store, Some(imports)).unwrap());
+        }
+
+        // changes made by one instance are visible to the other one and to the host
+        let (a, b) = (&instances[0], &instances[1]);
+        a.exported_func::<(), ()>(&store, "write").unwrap().call(&mut store, ()).unwrap();
+        assert_eq!(b.exported_func::<(), i32>(&store, "read").unwrap().call(&mut store, ()).unwrap(), 43);
+        assert_eq!(b.exported_func::<(), i32>(&store, "call").unwrap().call(&mut store, ()).unwrap(), 7);
+
+        assert_eq!(memory.get(&store).unwrap().load(0, 4).unwrap(), 42i32.to_le_bytes());
+        assert_eq!(counter.get(&store).unwrap(), WasmValue::I32(1));
+        let func = table.get_func(&store, 1).unwrap().unwrap();
+        assert_eq!(func.call(&mut store, &[]).unwrap(), [WasmValue::I32(7)]);
+
+        counter.set(&mut store, WasmValue::I32(100)).unwrap();
+        memory.get_mut(&mut store).unwrap().store(0, 4, &1i32.to_le_bytes()).unwrap();
+        assert_eq!(a.exported_func::<(), i32>(&store, "read").unwrap().call(&mut store, ()).unwrap(), 101);
+
+        // handles belong to a single store
+        let other = Store::default();
+        assert!(matches!(memory.get(&other), Err(Error::InvalidStore)));
+        assert!(matches!(table.size(&other), Err(Error::InvalidStore)));
+        assert!(matches!(counter.get(&other), Err(Error::InvalidStore)));
+    }
+
+    #[test]
+    fn test_handle_types() {
+        let mut store = Store::default();
+        let ty = TableType::new(ValType::RefFunc, 1, Some(4));
+        let table = Table::new(&mut store, ty.clone(), WasmValue::RefNull(ValType::RefFunc)).unwrap();
+        assert_eq!(table.ty(&store).unwrap(), ty);
+        assert!(table.set(&mut store, 0, WasmValue::I32(1)).is_err());
+        assert!(table.set(&mut store, 0, WasmValue::RefFunc(100)).is_err());
+        assert_eq!(table.grow(&mut store, 3, WasmValue::RefNull(ValType::RefFunc)).unwrap(), 1);
+        assert!(table.grow(&mut store, 1, WasmValue::RefNull(ValType::RefFunc)).is_err());
+
+        let counter =
+            Global::new(&mut store, GlobalType { mutable: false, ty: ValType::I64 }, WasmValue::I64(1)).unwrap();
+        assert!(counter.set(&mut store, WasmValue::I64(2)).is_err());
+        assert!(Global::new(&mut store, GlobalType { mutable: false, ty: ValType::I64 }, WasmValue::I32(1)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_func_refs() {
+        let mut store = Store::default();
+        let f = FuncHandle::wrap(&mut store, || 7).unwrap();
+        let ty = GlobalType { mutable: true, ty: ValType::RefFunc };
+        assert!(Global::new(&mut store, ty, WasmValue::RefFunc(999)).is_err());
+
+        let global = Global::new(&mut store, ty, f.func_ref()).unwrap();
+        assert!(global.set(&mut store, WasmValue::RefFunc(999)).is_err());
+        assert_eq!(global.get(&store).unwrap(), f.func_ref());
+
+        // the guest can't call a function that doesn't exist
+        let module = crate::test_util::module(
+            r#"(module
+                (import "env" "f" (global $f (mut funcref)))
+                (table 1 funcref)
+                (func (export "call") (result i32)
+                    (table.set (i32.const 0) (global.get $f))
+                    (call_indirect (result i32) (i32.const 0))))"#,
+        );
+        let mut imports = Imports::new();
+        imports.define("env", "f", Extern::global(WasmValue::RefFunc(999), true)).unwrap();
+        let res = module.clone().instantiate(&mut store, Some(imports));
+        assert!(matches!(res, Err(Error::Other(e)) if e == "invalid function reference"));
+
+        let mut imports = Imports::new();
+        imports.define("env", "f", Extern::from(global)).unwrap();
+        let instance = module.instantiate(&mut store, Some(imports)).unwrap();
+        assert_eq!(instance.exported_func::<(), i32>(&store, "call").unwrap().call(&mut store, ()).unwrap(), 7);
+    }
+}
@@ -0,0 +1,300 @@
+use alloc::vec::Vec;
+use tinywasm_types::{FuncType, ValType, WasmValue};
+
+use crate::func::{FromWasmValueTuple, IntoWasmValueTuple, ValTypesFromTuple, WasmType};
+use crate::interpreter::stack::ValueStack;
+use crate::interpreter::{StackHeight, StackLocation, TinyWasmValue};
+use crate::{Error, FuncContext, Function, MaybeSendSync, Result};
+
+/// The params and results of a host function call
+///
+/// When called from WebAssembly, params are read directly from the value stack and results are pushed onto it,
+/// so typed host functions don't allocate or copy values into temporary buffers.
+pub(crate) enum HostCall<'a> {
+    Stack { values: &'a mut ValueStack, start: StackLocation, next: StackLocation },
+    Buffer { params: &'a [WasmValue], next: usize, results: &'a mut Vec<WasmValue> },
+}
+
+impl<'a> HostCall<'a> {
+    // a call from WebAssembly, with the params on top of the value stack
+    #[inline]
+    pub(crate) fn stack(values: &'a mut ValueStack, params: StackHeight) -> Self {
+        let height = values.height();
+        let start = StackLocation {
+            s32: height.s32 - u32::from(params.s32),
+            s64: height.s64 - u32::from(params.s64),
+            s128: height.s128 - u32::from(params.s128),
+            sref: height.sref - u32::from(params.sref),
+        };
+        Self::Stack { values, start, next: start }
+    }
+
+    // a call from the host, with the results written to `results`
+    pub(crate) fn buffer(params: &'a [WasmValue], results: &'a mut Vec<WasmValue>) -> Self {
+        Self::Buffer { params, next: 0, results }
+    }
+
+    // read the next param, which has to be of type `ty`
+    #[inline]
+    pub(crate) fn param(&mut self, ty: ValType) -> Option<WasmValue> {
+        match self {
+            Self::Stack { values, next, .. } => {
+                let value = match ty {
+                    ValType::I32 | ValType::F32 => TinyWasmValue::Value32(*values.stack_32.get(next.s32 as usize)?),
+                    ValType::I64 | ValType::F64 => TinyWasmValue::Value64(*values.stack_64.get(next.s64 as usize)?),
+                    ValType::V128 => TinyWasmValue::Value128(*values.stack_128.get(next.s128 as usize)?),
+                    ValType::RefExtern | ValType::RefFunc => {
+                        TinyWasmValue::ValueRef(*values.stack_ref.get(next.sref as usize)?)
+                    }
+                };
+
+                let height = StackHeight::from(ty);
+                next.s32 += u32::from(height.s32);
+                next.s64 += u32::from(height.s64);
+                next.s128 += u32::from(height.s128);
+                next.sref += u32::from(height.sref);
+                Some(value.attach_type(ty))
+            }
+            Self::Buffer { params, next, .. } => {
+                let value = params.get(*next).copied()?;
+                *next += 1;
+                Some(value)
+            }
+        }
+    }
+
+    // replace the params with the results
+    #[inline]
+    pub(crate) fn write_results(&mut self, results: impl IntoWasmValueTuple) {
+        match self {
+            Self::Stack { values, start, .. } => {
+                values.truncate_keep(*start, StackHeight::default());
+                results.write_wasm_values(|value| values.push_dyn((&value).into()));
+            }
+            Self::Buffer { results: buffer, .. } => {
+                buffer.clear();
+                results.write_wasm_values(|value| buffer.push(value));
+            }
+        }
+    }
+
+    // call a host function that takes its params as a slice and returns its results as a vector
+    pub(crate) fn call_untyped(
+        &mut self,
+        param_types: &[ValType],
+        func: impl FnOnce(&[WasmValue]) -> Result<Vec<WasmValue>>,
+    ) -> Result<()> {
+        match self {
+            Self::Stack { values, .. } => {
+                let args = values.pop_values(param_types);
+                let results = func(&args)?;
+                values.extend_from_wasmvalues(&results);
+            }
+            Self::Buffer { params, results, .. } => **results = func(params)?,
+        }
+        Ok(())
+    }
+}
+
+/// A Rust closure that can be used as a host function
+///
+/// Implemented for closures with up to 16 [`WasmType`] parameters, optionally taking a [`FuncContext`]
+/// as their first argument, and returning a [`HostFuncResult`]:
+///
+/// ```rust
+/// use tinywasm::{Extern, ExternRef, FuncContext, Trap};
+///
+/// let add = Extern::wrap(|a: i32, b: i32| a + b);
+/// let divide = Extern::wrap(|a: u64, b: u64| a.checked_div(b).ok_or(Trap::DivisionByZero));
+/// let len = Extern::wrap(|ctx: FuncContext<'_>, s: ExternRef| ctx.extern_data::<String>(s).map_or(0, |s| s.len() as u32));
+/// ```
+///
+/// Closures that only return `Ok(..)` need an explicit return type, as the error type can't be inferred.
+pub trait IntoHostFunc<Params, Results>: MaybeSendSync + 'static {
+    /// Convert the closure into a host function
+    fn into_host_func(self) -> Function;
+}
+
+/// The return type of a closure used as a host function
+///
+/// Implemented for `()`, single [`WasmType`] values, tuples of up to 16 values,
+/// and `Result`s of these with an error that converts into [`Error`]. Custom errors can be returned using [`Error::host`].
+pub trait HostFuncResult {
+    /// The values returned to WebAssembly
+    type Values: IntoWasmValueTuple + ValTypesFromTuple;
+
+    /// Get the returned values, or the error the host function failed with
+    fn into_host_result(self) -> Result<Self::Values>;
+}
+
+impl<T: IntoWasmValueTuple + ValTypesFromTuple> HostFuncResult for T {
+    type Values = T;
+
+    #[inline]
+    fn into_host_result(self) -> Result<T> {
+        Ok(self)
+    }
+}
+
+impl<T: IntoWasmValueTuple + ValTypesFromTuple, E: Into<Error>> HostFuncResult for core::result::Result<T, E> {
+    type Values = T;
+
+    #[inline]
+    fn into_host_result(self) -> Result<T> {
+        self.map_err(Into::into)
+    }
+}
+
+macro_rules! impl_into_host_func {
+    ($($P:ident),*) => {
+        impl<F, $($P: WasmType,)* R: HostFuncResult> IntoHostFunc<($($P,)*), R> for F
+        where
+            F: Fn($($P),*) -> R + MaybeSendSync + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_host_func(self) -> Function {
+                let ty = FuncType { params: <($($P,)*)>::val_types(), results: R::Values::val_types() };
+                Function::host_raw(ty, move |_ctx, call| {
+                    let ($($P,)*) = <($($P,)*)>::from_wasm_values(|ty| call.param(ty))?;
+                    call.write_results(self($($P),*).into_host_result()?);
+                    Ok(())
+                })
+            }
+        }
+
+        impl<F, $($P: WasmType,)* R: HostFuncResult> IntoHostFunc<(FuncContext<'static>, $($P,)*), R> for F
+        where
+            F: Fn(FuncContext<'_>, $($P),*) -> R + MaybeSendSync + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn into_host_func(self) -> Function {
+                let ty = FuncType { params: <($($P,)*)>::val_types(), results: R::Values::val_types() };
+                Function::host_raw(ty, move |ctx, call| {
+                    let ($($P,)*) = <($($P,)*)>::from_wasm_values(|ty| call.param(ty))?;
+                    call.write_results(self(ctx, $($P),*).into_host_result()?);
+                    Ok(())
+                })
+            }
+        }
+    };
+}
+
+impl_into_host_func!();
+impl_into_host_func!(T1);
+impl_into_host_func!(T1, T2);
+impl_into_host_func!(T1, T2, T3);
+impl_into_host_func!(T1, T2, T3, T4);
+impl_into_host_func!(T1, T2, T3, T4, T5);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_into_host_func!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+
+#[cfg(test)]
+mod host_func_tests {
+    use crate::types::{FuncType, ValType, WasmValue};
+    use crate::{ExternRef, FuncContext, FuncHandle, Store};
+    use alloc::boxed::Box;
+
+    #[test]
+    fn test_wrap() {
+        let mut store = Store::default();
+        let sub = FuncHandle::wrap(&mut store, |a: u32, b: i64| (a as i64 - b, a)).unwrap();
+        let ty = FuncType {
+            params: Box::new([ValType::I32, ValType::I64]),
+            results: Box::new([ValType::I64, ValType::I32]),
+        };
+        assert_eq!(sub.ty(), &ty);
+        let res = sub.call(&mut store, &[WasmValue::I32(10), WasmValue::I64(3)]).unwrap();
+        assert_eq!(res, [WasmValue::I64(7), WasmValue::I32(10)]);
+
+        let object = store.new_extern_ref(5u8).unwrap();
+        let get = FuncHandle::wrap(&mut store, |ctx: FuncContext<'_>, r: Option<ExternRef>| {
+            r.and_then(|r| ctx.extern_data::<u8>(r).copied()).map(u32::from).ok_or(crate::Error::Other("null".into()))
+        })
+        .unwrap();
+        assert_eq!(get.call(&mut store, &[object.into()]).unwrap(), [WasmValue::I32(5)]);
+        assert!(get.call(&mut store, &[WasmValue::RefNull(ValType::RefExtern)]).is_err());
+    }
+
+    #[cfg(feature = "parser")]
+    fn instantiate(store: &mut Store, wat: &str, name: &str, func: crate::Extern) -> crate::ModuleInstance {
+        let mut imports = crate::Imports::new();
+        imports.define("env", name, func).unwrap();
+        crate::test_util::module(wat).instantiate(store, Some(imports)).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_stack_call() {
+        // the values below the params have to stay in place on each of the stacks
+        let wat = r#"(module
+            (import "env" "mixed" (func $mixed (param i32 i64 i32 f64) (result i64 i32)))
+            (func (export "run") (result f64 i64 i64 i32 i64 i32)
+                (f64.const 0.5) (i64.const 7)
+                (call $mixed (i32.const 1) (i64.const 2) (i32.const 3) (f64.const 4.5))
+                (call $mixed (i32.const 10) (i64.const 20) (i32.const 30) (f64.const 1.0))))"#;
+
+        let mut store = Store::default();
+        let mixed = crate::Extern::wrap(|a: i32, b: i64, c: i32, d: f64| (b + (d * 10.0) as i64, a - c));
+        let instance = instantiate(&mut store, wat, "mixed", mixed);
+        let run = instance.exported_func::<(), (f64, i64, i64, i32, i64, i32)>(&store, "run").unwrap();
+        assert_eq!(run.call(&mut store, ()).unwrap(), (0.5, 7, 47, -2, 30, -20));
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_stack_call_refs() {
+        use crate::FuncRef;
+
+        let wat = r#"(module
+            (import "env" "refs" (func $refs (param v128 externref i32 funcref) (result funcref v128 externref)))
+            (func $f)
+            (elem declare func $f)
+            (func (export "run") (param v128 externref) (result i32 funcref v128 externref)
+                (i32.const 1)
+                (call $refs (local.get 0) (local.get 1) (i32.const 5) (ref.func $f))))"#;
+
+        let mut store = Store::default();
+        let refs =
+            crate::Extern::wrap(|v: u128, e: Option<ExternRef>, n: i32, f: Option<FuncRef>| (f, v + n as u128, e));
+        let instance = instantiate(&mut store, wat, "refs", refs);
+        let run = instance
+            .exported_func::<(u128, Option<ExternRef>), (i32, Option<FuncRef>, u128, Option<ExternRef>)>(&store, "run");
+        let run = run.unwrap();
+
+        let object = store.new_extern_ref(()).unwrap();
+        let (one, func, v, e) = run.call(&mut store, ((2 << 64) + 1, Some(object))).unwrap();
+        assert_eq!((one, v, e), (1, (2 << 64) + 6, Some(object)));
+        assert!(func.is_some());
+        assert_eq!(run.call(&mut store, (0, None)).unwrap().3, None);
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_stack_call_error() {
+        let wat = r#"(module
+            (import "env" "check" (func $check (param i32) (result i32)))
+            (func (export "run") (param i32) (result i64 i32)
+                (i64.const 9) (call $check (local.get 0))))"#;
+
+        let mut store = Store::default();
+        let check =
+            crate::Extern::wrap(|n: i32| if n < 0 { Err(crate::Error::Other("negative".into())) } else { Ok(n * 2) });
+        let instance = instantiate(&mut store, wat, "check", check);
+        let run = instance.exported_func::<i32, (i64, i32)>(&store, "run").unwrap();
+
+        // the failed call doesn't leave values behind
+        assert!(matches!(run.call(&mut store, -1), Err(crate::Error::Other(e)) if e == "negative"));
+        assert_eq!(run.call(&mut store, 4).unwrap(), (9, 8));
+        assert!(run.call(&mut store, -1).is_err());
+        assert_eq!(run.call(&mut store, 5).unwrap(), (9, 10));
+    }
+}
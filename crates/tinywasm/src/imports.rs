@@ -1,12 +1,13 @@
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
-use alloc::rc::Rc;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::fmt::Debug;
 
 use crate::func::{FromWasmValueTuple, IntoWasmValueTuple, ValTypesFromTuple};
-use crate::{log, LinkingError, MemoryRef, MemoryRefMut, Result};
+use crate::host_func::HostCall;
+use crate::interpreter::StackHeight;
+use crate::{log, Error, ExternType, LinkingError, MemoryRef, MemoryRefMut, Rc, Result};
 use tinywasm_types::*;
 
 /// The internal representation of a function
@@ -26,12 +27,46 @@ impl Function {
             Self::Wasm(f) => &f.ty,
         }
     }
+
+    // create a host function
+    pub(crate) fn host(
+        ty: &FuncType,
+        func: impl Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + MaybeSendSync + 'static,
+    ) -> Self {
+        let params = ty.params.clone();
+        Self::host_raw(ty.clone(), move |ctx, call| call.call_untyped(&params, |args| func(ctx, args)))
+    }
+
+    // create a host function with typed params and results
+    pub(crate) fn typed_host<P, R>(func: impl Fn(FuncContext<'_>, P) -> Result<R> + MaybeSendSync + 'static) -> Self
+    where
+        P: FromWasmValueTuple + ValTypesFromTuple,
+        R: IntoWasmValueTuple + ValTypesFromTuple + Debug,
+    {
+        let ty = tinywasm_types::FuncType { params: P::val_types(), results: R::val_types() };
+        Self::host_raw(ty, move |ctx, call| {
+            let args = P::from_wasm_values(|ty| call.param(ty))?;
+            call.write_results(func(ctx, args)?);
+            Ok(())
+        })
+    }
+
+    // create a host function that reads its params from and writes its results to the call directly
+    pub(crate) fn host_raw(
+        ty: FuncType,
+        func: impl Fn(FuncContext<'_>, &mut HostCall<'_>) -> Result<()> + MaybeSendSync + 'static,
+    ) -> Self {
+        let params = StackHeight::from(&ty.params[..]);
+        Self::Host(Rc::new(HostFunction { func: Box::new(func), ty, params }))
+    }
 }
 
 /// A host function
 pub struct HostFunction {
     pub(crate) ty: tinywasm_types::FuncType,
     pub(crate) func: HostFuncInner,
+    // the number of params on each value stack
+    pub(crate) params: StackHeight,
 }
 
 impl HostFunction {
@@ -42,11 +77,48 @@ impl HostFunction {
 
     /// Call the function
     pub fn call(&self, ctx: FuncContext<'_>, args: &[WasmValue]) -> Result<Vec<WasmValue>> {
-        (self.func)(ctx, args)
+        let mut results = Vec::with_capacity(self.ty.results.len());
+        (self.func)(ctx, &mut HostCall::buffer(args, &mut results))?;
+        Ok(results)
     }
 }
 
-pub(crate) type HostFuncInner = Box<dyn Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>>>;
+#[cfg(not(feature = "sync"))]
+pub(crate) type HostFuncInner = Box<dyn Fn(FuncContext<'_>, &mut HostCall<'_>) -> Result<()>>;
+#[cfg(feature = "sync")]
+pub(crate) type HostFuncInner = Box<dyn Fn(FuncContext<'_>, &mut HostCall<'_>) -> Result<()> + Send + Sync>;
+
+/// Implemented for all types that are `Send` and `Sync`, or for all types if the `sync` feature is disabled
+///
+/// Host functions have to implement this trait, so they can be shared between threads with the `sync` feature.
+#[cfg(feature = "sync")]
+pub trait MaybeSendSync: Send + Sync {}
+#[cfg(feature = "sync")]
+impl<T: Send + Sync> MaybeSendSync for T {}
+
+/// Implemented for all types that are `Send` and `Sync`, or for all types if the `sync` feature is disabled
+///
+/// Host functions have to implement this trait, so they can be shared between threads with the `sync` feature.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSendSync {}
+#[cfg(not(feature = "sync"))]
+impl<T> MaybeSendSync for T {}
+
+/// Implemented for all types that are `Send`, or for all types if the `sync` feature is disabled
+///
+/// Data attached to a [`crate::Store`] has to implement this trait, so the store can be sent between threads.
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "sync")]
+impl<T: Send> MaybeSend for T {}
+
+/// Implemented for all types that are `Send`, or for all types if the `sync` feature is disabled
+///
+/// Data attached to a [`crate::Store`] has to implement this trait, so the store can be sent between threads.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "sync"))]
+impl<T> MaybeSend for T {}
 
 /// The context of a host-function call
 #[derive(Debug)]
@@ -66,19 +138,55 @@ impl FuncContext<'_> {
         self.store
     }
 
+    /// Get a reference to the value of type `T` attached to the store
+    ///
+    /// See [`crate::Store::insert_data`]
+    pub fn data<T: core::any::Any>(&self) -> Option<&T> {
+        self.store.data()
+    }
+
+    /// Get a mutable reference to the value of type `T` attached to the store
+    ///
+    /// See [`crate::Store::insert_data`]
+    pub fn data_mut<T: core::any::Any>(&mut self) -> Option<&mut T> {
+        self.store.data_mut()
+    }
+
+    /// Get a reference to a host object, if it still exists and is of type `T`
+    ///
+    /// See [`crate::Store::new_extern_ref`]
+    pub fn extern_data<T: core::any::Any>(&self, extern_ref: crate::ExternRef) -> Option<&T> {
+        self.store.extern_data(extern_ref)
+    }
+
+    /// Get a mutable reference to a host object, if it still exists and is of type `T`
+    ///
+    /// See [`crate::Store::new_extern_ref`]
+    pub fn extern_data_mut<T: core::any::Any>(&mut self, extern_ref: crate::ExternRef) -> Option<&mut T> {
+        self.store.extern_data_mut(extern_ref)
+    }
+
     /// Get a reference to the module instance
+    ///
+    /// Panics if the function wasn't called by a module instance, e.g. when a function created using
+    /// [`crate::FuncHandle::new`] is called directly by the host, or if the instance was removed and reclaimed.
     pub fn module(&self) -> crate::ModuleInstance {
-        self.store.get_module_instance_raw(self.module_addr)
+        self.store.get_module_instance_raw(self.module_addr).expect("module instance was reclaimed")
     }
 
     /// Get a reference to an exported memory
     pub fn exported_memory(&mut self, name: &str) -> Result<MemoryRef<'_>> {
-        self.module().exported_memory(self.store, name)
+        self.caller()?.exported_memory(self.store, name)
     }
 
     /// Get a reference to an exported memory
     pub fn exported_memory_mut(&mut self, name: &str) -> Result<MemoryRefMut<'_>> {
-        self.module().exported_memory_mut(self.store, name)
+        self.caller()?.exported_memory_mut(self.store, name)
+    }
+
+    fn caller(&self) -> Result<crate::ModuleInstance> {
+        let instance = self.store.get_module_instance(self.module_addr);
+        instance.cloned().ok_or_else(|| Error::Other("function was not called by a module instance".to_string()))
     }
 }
 
@@ -116,6 +224,42 @@ pub enum Extern {
 
     /// A function
     Function(Function),
+
+    /// A memory in a store, shared by all instances importing it
+    MemoryHandle(crate::Memory),
+
+    /// A table in a store, shared by all instances importing it
+    TableHandle(crate::Table),
+
+    /// A global in a store, shared by all instances importing it
+    GlobalHandle(crate::Global),
+
+    /// A function in a store, e.g. an export of another instance
+    FuncHandle(crate::FuncHandle),
+}
+
+impl From<crate::FuncHandle> for Extern {
+    fn from(func: crate::FuncHandle) -> Self {
+        Self::FuncHandle(func)
+    }
+}
+
+impl From<crate::Memory> for Extern {
+    fn from(memory: crate::Memory) -> Self {
+        Self::MemoryHandle(memory)
+    }
+}
+
+impl From<crate::Table> for Extern {
+    fn from(table: crate::Table) -> Self {
+        Self::TableHandle(table)
+    }
+}
+
+impl From<crate::Global> for Extern {
+    fn from(global: crate::Global) -> Self {
+        Self::GlobalHandle(global)
+    }
 }
 
 impl Extern {
@@ -137,34 +281,34 @@ impl Extern {
     /// Create a new function import
     pub fn func(
         ty: &tinywasm_types::FuncType,
-        func: impl Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + 'static,
+        func: impl Fn(FuncContext<'_>, &[WasmValue]) -> Result<Vec<WasmValue>> + MaybeSendSync + 'static,
     ) -> Self {
-        Self::Function(Function::Host(Rc::new(HostFunction { func: Box::new(func), ty: ty.clone() })))
+        Self::Function(Function::host(ty, func))
     }
 
     /// Create a new typed function import
-    pub fn typed_func<P, R>(func: impl Fn(FuncContext<'_>, P) -> Result<R> + 'static) -> Self
+    pub fn typed_func<P, R>(func: impl Fn(FuncContext<'_>, P) -> Result<R> + MaybeSendSync + 'static) -> Self
     where
         P: FromWasmValueTuple + ValTypesFromTuple,
         R: IntoWasmValueTuple + ValTypesFromTuple + Debug,
     {
-        let inner_func = move |ctx: FuncContext<'_>, args: &[WasmValue]| -> Result<Vec<WasmValue>> {
-            let args = P::from_wasm_value_tuple(args)?;
-            let result = func(ctx, args)?;
-            Ok(result.into_wasm_value_tuple().to_vec())
-        };
+        Self::Function(Function::typed_host(func))
+    }
 
-        let ty = tinywasm_types::FuncType { params: P::val_types(), results: R::val_types() };
-        Self::Function(Function::Host(Rc::new(HostFunction { func: Box::new(inner_func), ty })))
+    /// Create a new function import from a Rust closure
+    ///
+    /// The closure can optionally take a [`FuncContext`] as its first argument, see [`IntoHostFunc`](crate::IntoHostFunc).
+    pub fn wrap<P, R>(func: impl crate::IntoHostFunc<P, R>) -> Self {
+        Self::Function(func.into_host_func())
     }
 
     /// Get the kind of the external value
     pub fn kind(&self) -> ExternalKind {
         match self {
-            Self::Global { .. } => ExternalKind::Global,
-            Self::Table { .. } => ExternalKind::Table,
-            Self::Memory { .. } => ExternalKind::Memory,
-            Self::Function { .. } => ExternalKind::Func,
+            Self::Global { .. } | Self::GlobalHandle(_) => ExternalKind::Global,
+            Self::Table { .. } | Self::TableHandle(_) => ExternalKind::Table,
+            Self::Memory { .. } | Self::MemoryHandle(_) => ExternalKind::Memory,
+            Self::Function { .. } | Self::FuncHandle(_) => ExternalKind::Func,
         }
     }
 }
@@ -182,7 +326,7 @@ impl From<&Import> for ExternName {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 /// Imports for a module instance
 ///
 /// This is used to link a module instance to its imports
@@ -221,6 +365,43 @@ impl From<&Import> for ExternName {
 pub struct Imports {
     values: BTreeMap<ExternName, Extern>,
     modules: BTreeMap<String, ModuleInstanceAddr>,
+    links: BTreeMap<ExternName, ExternVal>, // values already in the store, e.g. exports resolved by the linker
+    resolver: Option<Rc<dyn ImportResolver>>,
+}
+
+impl Debug for Imports {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Imports")
+            .field("values", &self.values)
+            .field("modules", &self.modules)
+            .field("links", &self.links)
+            .field("resolver", &self.resolver.is_some())
+            .finish()
+    }
+}
+
+/// A fallback for imports that are not defined in an [`Imports`] object
+///
+/// Consulted for every import that can't be found by name, given the module name, import name and type of the import.
+/// This can be used to create values on demand, e.g. stubs for functions that are never called,
+/// or proxies to a dynamic plugin registry. If `None` is returned, linking fails with [`LinkingError::UnknownImport`].
+///
+/// Resolvers are only used when instantiating with an [`Imports`] object. [`crate::Linker`] doesn't consult them,
+/// so imports that aren't defined in a linker always fail to link.
+///
+/// Implemented for closures, see [`Imports::set_resolver`].
+pub trait ImportResolver: MaybeSendSync {
+    /// Resolve an import, the returned value has to match the type of the import
+    fn resolve(&self, module: &str, name: &str, ty: &ExternType) -> Option<Extern>;
+}
+
+impl<F> ImportResolver for F
+where
+    F: Fn(&str, &str, &ExternType) -> Option<Extern> + MaybeSendSync,
+{
+    fn resolve(&self, module: &str, name: &str, ty: &ExternType) -> Option<Extern> {
+        self(module, name, ty)
+    }
 }
 
 pub(crate) enum ResolvedExtern<S, V> {
@@ -239,18 +420,80 @@ impl ResolvedImports {
     pub(crate) fn new() -> Self {
         Self { globals: Vec::new(), tables: Vec::new(), memories: Vec::new(), funcs: Vec::new() }
     }
+
+    // add a host value to the store, the type has to be checked using `Imports::check_extern` first
+    // handles are already in the store, their type is checked here since tables and memories can grow
+    pub(crate) fn push_extern(
+        &mut self,
+        store: &mut crate::Store,
+        module: &crate::Module,
+        import: &Import,
+        ex: &Extern,
+        idx: ModuleInstanceAddr,
+    ) -> Result<()> {
+        match ex {
+            Extern::Global { ty, val } => {
+                crate::handles::check_value(store, ty.ty, val)?;
+                self.globals.push(store.add_global(*ty, (*val).into(), idx)?)
+            }
+            Extern::Table { ty, init } => {
+                let init = crate::handles::table_element(store, ty.element_type, *init)?;
+                let addr = store.add_table(ty.clone(), idx)?;
+                store.get_table_mut(addr).elements.fill(init);
+                self.tables.push(addr);
+            }
+            Extern::Memory { ty } => self.memories.push(store.add_mem(*ty, idx)?),
+            Extern::Function(func) => self.funcs.push(store.add_func(func.clone(), idx)?),
+            Extern::MemoryHandle(memory) => {
+                Imports::link_store(store, module, import, ExternVal::Memory(memory.addr(store)?), self)?
+            }
+            Extern::TableHandle(table) => {
+                Imports::link_store(store, module, import, ExternVal::Table(table.addr(store)?), self)?
+            }
+            Extern::GlobalHandle(global) => {
+                Imports::link_store(store, module, import, ExternVal::Global(global.addr(store)?), self)?
+            }
+            Extern::FuncHandle(func) if func.store_id != store.id() => return Err(Error::InvalidStore),
+            Extern::FuncHandle(func) => Imports::link_store(store, module, import, ExternVal::Func(func.addr), self)?,
+        }
+        Ok(())
+    }
 }
 
 impl Imports {
     /// Create a new empty import set
     pub fn new() -> Self {
-        Imports { values: BTreeMap::new(), modules: BTreeMap::new() }
+        Imports { values: BTreeMap::new(), modules: BTreeMap::new(), links: BTreeMap::new(), resolver: None }
     }
 
     /// Merge two import sets
+    ///
+    /// The resolver of `other` is used if both have one.
     pub fn merge(mut self, other: Self) -> Self {
         self.values.extend(other.values);
         self.modules.extend(other.modules);
+        self.links.extend(other.links);
+        self.resolver = other.resolver.or(self.resolver);
+        self
+    }
+
+    /// Set a fallback for imports that are not defined, replacing the previous one
+    ///
+    /// ```rust
+    /// use tinywasm::{Error, Extern, ExternType, Imports};
+    ///
+    /// // functions that are never called don't need to be defined
+    /// let mut imports = Imports::new();
+    /// imports.set_resolver(|module: &str, name: &str, ty: &ExternType| match ty {
+    ///     ExternType::Func(ty) => {
+    ///         let name = format!("{module}.{name}");
+    ///         Some(Extern::func(ty, move |_, _| Err(Error::Other(format!("{name} is not implemented")))))
+    ///     }
+    ///     _ => None,
+    /// });
+    /// ```
+    pub fn set_resolver(&mut self, resolver: impl ImportResolver + 'static) -> &mut Self {
+        self.resolver = Some(Rc::new(resolver));
         self
     }
 
@@ -268,6 +511,11 @@ impl Imports {
         Ok(self)
     }
 
+    // define an import using a value that is already in the store
+    pub(crate) fn define_link(&mut self, module: &str, name: &str, value: ExternVal) {
+        self.links.insert(ExternName { module: module.to_string(), name: name.to_string() }, value);
+    }
+
     pub(crate) fn take(
         &mut self,
         store: &mut crate::Store,
@@ -277,6 +525,9 @@ impl Imports {
         if let Some(v) = self.values.get(&name) {
             return Some(ResolvedExtern::Extern(v.clone()));
         }
+        if let Some(v) = self.links.get(&name) {
+            return Some(ResolvedExtern::Store(v.clone()));
+        }
         if let Some(addr) = self.modules.get(&name.module) {
             let instance = store.get_module_instance(*addr)?;
             return Some(ResolvedExtern::Store(instance.export_addr(&import.name)?));
@@ -285,7 +536,14 @@ impl Imports {
         None
     }
 
-    fn compare_types<T: Debug + PartialEq>(import: &Import, actual: &T, expected: &T) -> Result<()> {
+    // ask the resolver for an import that isn't defined
+    fn resolve(&self, module: &crate::Module, import: &Import) -> Result<Extern> {
+        let resolver = self.resolver.as_ref().ok_or_else(|| LinkingError::unknown_import(import))?;
+        let ty = module.import_type(import).ok_or_else(|| LinkingError::unknown_import(import))?;
+        resolver.resolve(&import.module, &import.name, &ty).ok_or_else(|| LinkingError::unknown_import(import).into())
+    }
+
+    pub(crate) fn compare_types<T: Debug + PartialEq>(import: &Import, actual: &T, expected: &T) -> Result<()> {
         if expected != actual {
             log::error!("failed to link import {}, expected {:?}, got {:?}", import.name, expected, actual);
             return Err(LinkingError::incompatible_import_type(import).into());
@@ -345,79 +603,146 @@ impl Imports {
         let mut imports = ResolvedImports::new();
 
         for import in &module.0.imports {
-            let val = self.take(store, import).ok_or_else(|| LinkingError::unknown_import(import))?;
+            let val = match self.take(store, import) {
+                Some(val) => val,
+                None => ResolvedExtern::Extern(self.resolve(module, import)?),
+            };
 
             match val {
                 // A link to something that needs to be added to the store
-                ResolvedExtern::Extern(ex) => match (ex, &import.kind) {
-                    (Extern::Global { ty, val }, ImportKind::Global(import_ty)) => {
-                        Self::compare_types(import, &ty, import_ty)?;
-                        imports.globals.push(store.add_global(ty, val.into(), idx)?);
-                    }
-                    (Extern::Table { ty, .. }, ImportKind::Table(import_ty)) => {
-                        Self::compare_table_types(import, &ty, import_ty)?;
-                        imports.tables.push(store.add_table(ty, idx)?);
-                    }
-                    (Extern::Memory { ty }, ImportKind::Memory(import_ty)) => {
-                        Self::compare_memory_types(import, &ty, import_ty, None)?;
-                        imports.memories.push(store.add_mem(ty, idx)?);
-                    }
-                    (Extern::Function(extern_func), ImportKind::Function(ty)) => {
-                        let import_func_type = module
-                            .0
-                            .func_types
-                            .get(*ty as usize)
-                            .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
-
-                        Self::compare_types(import, extern_func.ty(), import_func_type)?;
-                        imports.funcs.push(store.add_func(extern_func, idx)?);
-                    }
-                    _ => return Err(LinkingError::incompatible_import_type(import).into()),
-                },
+                ResolvedExtern::Extern(ex) => {
+                    Self::check_extern(module, import, &ex)?;
+                    imports.push_extern(store, module, import, &ex, idx)?;
+                }
 
                 // A link to something already in the store
-                ResolvedExtern::Store(val) => {
-                    // check if the kind matches
-                    if val.kind() != (&import.kind).into() {
-                        return Err(LinkingError::incompatible_import_type(import).into());
-                    }
-
-                    match (val, &import.kind) {
-                        (ExternVal::Global(global_addr), ImportKind::Global(ty)) => {
-                            let global = store.get_global(global_addr);
-                            Self::compare_types(import, &global.ty, ty)?;
-                            imports.globals.push(global_addr);
-                        }
-                        (ExternVal::Table(table_addr), ImportKind::Table(ty)) => {
-                            let table = store.get_table(table_addr);
-                            let mut kind = table.kind.clone();
-                            kind.size_initial = table.size() as u32;
-                            Self::compare_table_types(import, &kind, ty)?;
-                            imports.tables.push(table_addr);
-                        }
-                        (ExternVal::Memory(memory_addr), ImportKind::Memory(ty)) => {
-                            let mem = store.get_mem(memory_addr);
-                            let (size, kind) = { (mem.page_count, mem.kind) };
-                            Self::compare_memory_types(import, &kind, ty, Some(size))?;
-                            imports.memories.push(memory_addr);
-                        }
-                        (ExternVal::Func(func_addr), ImportKind::Function(ty)) => {
-                            let func = store.get_func(func_addr);
-                            let import_func_type = module
-                                .0
-                                .func_types
-                                .get(*ty as usize)
-                                .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
-
-                            Self::compare_types(import, func.func.ty(), import_func_type)?;
-                            imports.funcs.push(func_addr);
-                        }
-                        _ => return Err(LinkingError::incompatible_import_type(import).into()),
-                    }
-                }
+                ResolvedExtern::Store(val) => Self::link_store(store, module, import, val, &mut imports)?,
             }
         }
 
         Ok(imports)
     }
+
+    // check that a host value matches the type of an import
+    pub(crate) fn check_extern(module: &crate::Module, import: &Import, ex: &Extern) -> Result<()> {
+        match (ex, &import.kind) {
+            (Extern::Global { ty, .. }, ImportKind::Global(import_ty)) => Self::compare_types(import, ty, import_ty),
+            (Extern::Table { ty, .. }, ImportKind::Table(import_ty)) => {
+                Self::compare_table_types(import, ty, import_ty)
+            }
+            (Extern::Memory { ty }, ImportKind::Memory(import_ty)) => {
+                Self::compare_memory_types(import, ty, import_ty, None)
+            }
+            (Extern::Function(extern_func), ImportKind::Function(ty)) => {
+                let import_func_type = module
+                    .0
+                    .func_types
+                    .get(*ty as usize)
+                    .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
+
+                Self::compare_types(import, extern_func.ty(), import_func_type)
+            }
+            (
+                Extern::MemoryHandle(_) | Extern::TableHandle(_) | Extern::GlobalHandle(_) | Extern::FuncHandle(_),
+                kind,
+            ) => match ex.kind() == kind.into() {
+                true => Ok(()),
+                false => Err(LinkingError::incompatible_import_type(import).into()),
+            },
+            _ => Err(LinkingError::incompatible_import_type(import).into()),
+        }
+    }
+
+    // check that a value already in the store matches the type of an import and add it to the resolved imports
+    pub(crate) fn link_store(
+        store: &crate::Store,
+        module: &crate::Module,
+        import: &Import,
+        val: ExternVal,
+        imports: &mut ResolvedImports,
+    ) -> Result<()> {
+        // check if the kind matches
+        if val.kind() != (&import.kind).into() {
+            return Err(LinkingError::incompatible_import_type(import).into());
+        }
+
+        match (val, &import.kind) {
+            (ExternVal::Global(global_addr), ImportKind::Global(ty)) => {
+                let global = store.get_global(global_addr);
+                Self::compare_types(import, &global.ty, ty)?;
+                imports.globals.push(global_addr);
+            }
+            (ExternVal::Table(table_addr), ImportKind::Table(ty)) => {
+                let table = store.get_table(table_addr);
+                let mut kind = table.kind.clone();
+                kind.size_initial = table.size() as u32;
+                Self::compare_table_types(import, &kind, ty)?;
+                imports.tables.push(table_addr);
+            }
+            (ExternVal::Memory(memory_addr), ImportKind::Memory(ty)) => {
+                let mem = store.get_mem(memory_addr);
+                let (size, kind) = { (mem.page_count, mem.kind) };
+                Self::compare_memory_types(import, &kind, ty, Some(size))?;
+                imports.memories.push(memory_addr);
+            }
+            (ExternVal::Func(func_addr), ImportKind::Function(ty)) => {
+                let func = store.get_func(func_addr);
+                let import_func_type = module
+                    .0
+                    .func_types
+                    .get(*ty as usize)
+                    .ok_or_else(|| LinkingError::incompatible_import_type(import))?;
+
+                Self::compare_types(import, func.func.ty(), import_func_type)?;
+                imports.funcs.push(func_addr);
+            }
+            _ => return Err(LinkingError::incompatible_import_type(import).into()),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod imports_tests {
+    use super::*;
+    use crate::Store;
+    use alloc::format;
+
+    const WAT: &str = r#"(module
+        (import "env" "used" (func $used (result i32)))
+        (import "env" "unused" (func $unused (param i64)))
+        (func (export "run") (result i32) (call $used)))"#;
+
+    #[test]
+    fn test_resolver_stub() {
+        let mut imports = Imports::new();
+        imports.define("env", "used", Extern::wrap(|| 7)).unwrap();
+        imports.set_resolver(|module: &str, name: &str, ty: &ExternType| match ty {
+            ExternType::Func(ty) => {
+                let name = format!("{module}.{name}");
+                Some(Extern::func(ty, move |_, _| Err(Error::Other(format!("{name} is not implemented")))))
+            }
+            _ => None,
+        });
+
+        // the resolver is only asked for imports that aren't defined
+        let mut store = Store::default();
+        let instance = crate::test_util::module(WAT).instantiate(&mut store, Some(imports)).unwrap();
+        assert_eq!(instance.exported_func::<(), i32>(&store, "run").unwrap().call(&mut store, ()).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_resolver_unknown_import() {
+        let mut imports = Imports::new();
+        imports.define("env", "used", Extern::wrap(|| 7)).unwrap();
+        imports.set_resolver(|_: &str, _: &str, _: &ExternType| None);
+
+        let mut store = Store::default();
+        let err = crate::test_util::module(WAT).instantiate(&mut store, Some(imports)).unwrap_err();
+        let Error::Linker(LinkingError::UnknownImport { module, name }) = err else {
+            panic!("unexpected error: {err}")
+        };
+        assert_eq!((module.as_str(), name.as_str()), ("env", "unused"));
+    }
 }
@@ -1,12 +1,15 @@
-use alloc::{boxed::Box, format, rc::Rc, string::ToString};
+use alloc::{boxed::Box, format, string::ToString};
 use tinywasm_types::*;
 
 use crate::func::{FromWasmValueTuple, IntoWasmValueTuple};
-use crate::{Error, FuncHandle, FuncHandleTyped, Imports, MemoryRef, MemoryRefMut, Module, Result, Store};
+use crate::{
+    Error, Extern, FuncHandle, FuncHandleTyped, Global, GlobalRef, Imports, Memory, MemoryRef, MemoryRefMut, Module,
+    Rc, ResolvedImports, Result, Store, Table, TableRef,
+};
 
 /// An instanciated WebAssembly module
 ///
-/// Backed by an Rc, so cloning is cheap
+/// Backed by an Rc (or an Arc with the `sync` feature), so cloning is cheap
 ///
 /// See <https://webassembly.github.io/spec/core/exec/runtime.html#module-instances>
 #[derive(Debug, Clone)]
@@ -32,6 +35,8 @@ pub(crate) struct ModuleInstanceInner {
     pub(crate) func_start: Option<FuncAddr>,
     pub(crate) imports: Box<[Import]>,
     pub(crate) exports: Box<[Export]>,
+    pub(crate) line_table: Option<LineTable>,
+    pub(crate) func_names: Box<[(FuncAddr, Box<str>)]>,
 }
 
 impl ModuleInstance {
@@ -41,11 +46,13 @@ impl ModuleInstance {
         self.0 = other.0;
     }
 
+    // reclaimed instances can't be swapped to, since their functions were already freed
     #[inline]
-    pub(crate) fn swap_with(&mut self, other_addr: ModuleInstanceAddr, store: &mut Store) {
+    pub(crate) fn swap_with(&mut self, other_addr: ModuleInstanceAddr, store: &mut Store) -> Result<()> {
         if other_addr != self.id() {
-            self.swap(store.get_module_instance_raw(other_addr))
+            self.swap(store.get_module_instance_raw(other_addr).ok_or(Error::InstanceRemoved)?)
         }
+        Ok(())
     }
 
     /// Get the module instance's address
@@ -57,28 +64,39 @@ impl ModuleInstance {
     /// Instantiate the module in the given store
     ///
     /// See <https://webassembly.github.io/spec/core/exec/modules.html#exec-instantiation>
-    pub fn instantiate(store: &mut Store, module: Module, imports: Option<Imports>) -> Result<Self> {
+    pub fn instantiate(store: &mut Store, mut module: Module, imports: Option<Imports>) -> Result<Self> {
+        let idx = store.next_module_instance_idx();
+        let addrs = imports.unwrap_or_default().link(store, &module, idx)?;
+        let funcs = core::mem::take(&mut module.0.funcs).into_vec().into_iter().map(Rc::new);
+        Self::instantiate_resolved(store, &module, funcs, addrs)
+    }
+
+    // instantiate a module with already resolved imports, the module's own functions are passed separately
+    pub(crate) fn instantiate_resolved(
+        store: &mut Store,
+        module: &Module,
+        funcs: impl IntoIterator<Item = Rc<WasmFunction>>,
+        mut addrs: ResolvedImports,
+    ) -> Result<Self> {
         // This doesn't completely follow the steps in the spec, but the end result is the same
         // Constant expressions are evaluated directly where they are used, so we
         // don't need to create a auxiliary frame etc.
 
         let idx = store.next_module_instance_idx();
-        let mut addrs = imports.unwrap_or_default().link(store, &module, idx)?;
+        addrs.funcs.extend(store.init_funcs(funcs, idx)?);
+        addrs.tables.extend(store.init_tables(&module.0.table_types, idx)?);
+        addrs.memories.extend(store.init_memories(&module.0.memory_types, idx)?);
 
-        addrs.funcs.extend(store.init_funcs(module.0.funcs.into(), idx)?);
-        addrs.tables.extend(store.init_tables(module.0.table_types.into(), idx)?);
-        addrs.memories.extend(store.init_memories(module.0.memory_types.into(), idx)?);
-
-        let global_addrs = store.init_globals(addrs.globals, module.0.globals.into(), &addrs.funcs, idx)?;
+        let global_addrs = store.init_globals(addrs.globals, &module.0.globals, &addrs.funcs, idx)?;
         let (elem_addrs, elem_trapped) =
             store.init_elements(&addrs.tables, &addrs.funcs, &global_addrs, &module.0.elements, idx)?;
-        let (data_addrs, data_trapped) = store.init_datas(&addrs.memories, module.0.data.into(), idx)?;
+        let (data_addrs, data_trapped) = store.init_datas(&addrs.memories, &module.0.data, idx)?;
 
         let instance = ModuleInstanceInner {
             failed_to_instantiate: elem_trapped.is_some() || data_trapped.is_some(),
             store_id: store.id(),
             idx,
-            types: module.0.func_types,
+            types: module.0.func_types.clone(),
             func_addrs: addrs.funcs.into_boxed_slice(),
             table_addrs: addrs.tables.into_boxed_slice(),
             mem_addrs: addrs.memories.into_boxed_slice(),
@@ -86,8 +104,10 @@ impl ModuleInstance {
             elem_addrs,
             data_addrs,
             func_start: module.0.start_func,
-            imports: module.0.imports,
-            exports: module.0.exports,
+            imports: module.0.imports.clone(),
+            exports: module.0.exports.clone(),
+            line_table: module.0.line_table.clone(),
+            func_names: module.0.func_names.clone(),
         };
 
         let instance = ModuleInstance::new(instance);
@@ -99,17 +119,40 @@ impl ModuleInstance {
         }
     }
 
+    /// Reset the instance's memories, tables, globals and segments to their state right after instantiation
+    ///
+    /// Only instances created using [`Module::instantiate`] or [`crate::InstancePre::instantiate`] after calling
+    /// [`Store::enable_instance_reset`] can be reset, their initial state is the state after the start function ran.
+    /// Imported memories, tables and globals are owned by other instances and are not reset.
+    /// Resetting is usually much cheaper than creating a new instance, e.g. when using an instance per request.
+    pub fn reset(&self, store: &mut Store) -> Result<()> {
+        if self.0.store_id != store.id() {
+            return Err(Error::InvalidStore);
+        }
+
+        if store.is_removed(self.id()) {
+            return Err(Error::InstanceRemoved);
+        }
+
+        store.reset_instance(self)
+    }
+
     /// Get a export by name
     pub fn export_addr(&self, name: &str) -> Option<ExternVal> {
-        let exports = self.0.exports.iter().find(|e| e.name == name.into())?;
-        let addr = match exports.kind {
-            ExternalKind::Func => self.0.func_addrs.get(exports.index as usize)?,
-            ExternalKind::Table => self.0.table_addrs.get(exports.index as usize)?,
-            ExternalKind::Memory => self.0.mem_addrs.get(exports.index as usize)?,
-            ExternalKind::Global => self.0.global_addrs.get(exports.index as usize)?,
+        let export = self.0.exports.iter().find(|e| e.name == name.into())?;
+        self.extern_addr(export.kind, export.index)
+    }
+
+    // get the address of an item by its index in the module
+    pub(crate) fn extern_addr(&self, kind: ExternalKind, index: u32) -> Option<ExternVal> {
+        let addr = match kind {
+            ExternalKind::Func => self.0.func_addrs.get(index as usize)?,
+            ExternalKind::Table => self.0.table_addrs.get(index as usize)?,
+            ExternalKind::Memory => self.0.mem_addrs.get(index as usize)?,
+            ExternalKind::Global => self.0.global_addrs.get(index as usize)?,
         };
 
-        Some(ExternVal::new(exports.kind, *addr))
+        Some(ExternVal::new(kind, *addr))
     }
 
     #[inline]
@@ -127,6 +170,12 @@ impl ModuleInstance {
         &self.0.func_addrs
     }
 
+    // get the name of a function from the name section, using the module's function index
+    pub(crate) fn func_name(&self, idx: FuncAddr) -> Option<&str> {
+        let pos = self.0.func_names.binary_search_by_key(&idx, |(idx, _)| *idx).ok()?;
+        Some(&self.0.func_names[pos].1)
+    }
+
     // resolve a function address to the global store address
     #[inline]
     pub(crate) fn resolve_func_addr(&self, addr: FuncAddr) -> FuncAddr {
@@ -175,7 +224,13 @@ impl ModuleInstance {
         };
 
         let ty = store.get_func(func_addr).func.ty();
-        Ok(FuncHandle { addr: func_addr, module_addr: self.id(), name: Some(name.to_string()), ty: ty.clone() })
+        Ok(FuncHandle {
+            store_id: store.id(),
+            addr: func_addr,
+            module_addr: self.id(),
+            name: Some(name.to_string()),
+            ty: ty.clone(),
+        })
     }
 
     /// Get a typed exported function by name
@@ -208,6 +263,60 @@ impl ModuleInstance {
         self.memory_mut(store, mem_addr)
     }
 
+    /// Get all exports of the instance
+    ///
+    /// Functions, memories, tables and globals are returned as handles, which can also be imported by other instances.
+    pub fn exports<'a>(&'a self, store: &'a Store) -> Result<impl Iterator<Item = (&'a str, Extern)> + 'a> {
+        if self.0.store_id != store.id() {
+            return Err(Error::InvalidStore);
+        }
+
+        Ok(self.0.exports.iter().filter_map(move |export| {
+            let value = match self.extern_addr(export.kind, export.index)? {
+                ExternVal::Func(addr) => Extern::FuncHandle(FuncHandle {
+                    store_id: store.id(),
+                    module_addr: self.id(),
+                    addr,
+                    ty: store.get_func(addr).func.ty().clone(),
+                    name: Some(export.name.to_string()),
+                }),
+                ExternVal::Table(addr) => Extern::TableHandle(Table::from_addr(store, addr)),
+                ExternVal::Memory(addr) => Extern::MemoryHandle(Memory::from_addr(store, addr)),
+                ExternVal::Global(addr) => Extern::GlobalHandle(Global::from_addr(store, addr)),
+            };
+
+            Some((&*export.name, value))
+        }))
+    }
+
+    /// Get an exported global by name
+    pub fn exported_global<'a>(&self, store: &'a mut Store, name: &str) -> Result<GlobalRef<'a>> {
+        if self.0.store_id != store.id() {
+            return Err(Error::InvalidStore);
+        }
+
+        let export = self.export_addr(name).ok_or_else(|| Error::Other(format!("Export not found: {name}")))?;
+        let ExternVal::Global(global_addr) = export else {
+            return Err(Error::Other(format!("Export is not a global: {name}")));
+        };
+
+        Ok(GlobalRef { global: Global::from_addr(store, global_addr), store })
+    }
+
+    /// Get an exported table by name
+    pub fn exported_table<'a>(&self, store: &'a mut Store, name: &str) -> Result<TableRef<'a>> {
+        if self.0.store_id != store.id() {
+            return Err(Error::InvalidStore);
+        }
+
+        let export = self.export_addr(name).ok_or_else(|| Error::Other(format!("Export not found: {name}")))?;
+        let ExternVal::Table(table_addr) = export else {
+            return Err(Error::Other(format!("Export is not a table: {name}")));
+        };
+
+        Ok(TableRef { table: Table::from_addr(store, table_addr), store })
+    }
+
     /// Get a memory by address
     pub fn memory<'a>(&self, store: &'a Store, addr: MemAddr) -> Result<MemoryRef<'a>> {
         let mem = store.get_mem(self.resolve_mem_addr(addr));
@@ -247,7 +356,13 @@ impl ModuleInstance {
         let func_inst = store.get_func(func_addr);
         let ty = func_inst.func.ty();
 
-        Ok(Some(FuncHandle { module_addr: self.id(), addr: func_addr, ty: ty.clone(), name: None }))
+        Ok(Some(FuncHandle {
+            store_id: store.id(),
+            module_addr: self.id(),
+            addr: func_addr,
+            ty: ty.clone(),
+            name: None,
+        }))
     }
 
     /// Invoke the start function of the module
@@ -2,7 +2,7 @@
 #[allow(unused_imports)]
 use super::no_std_floats::NoStdFloatExt;
 
-use alloc::{format, rc::Rc, string::ToString};
+use alloc::{format, string::ToString, vec::Vec};
 use core::ops::ControlFlow;
 use interpreter::stack::CallFrame;
 use tinywasm_types::*;
@@ -10,6 +10,7 @@ use tinywasm_types::*;
 use super::num_helpers::*;
 use super::stack::{BlockFrame, BlockType, Stack};
 use super::values::*;
+use crate::host_func::HostCall;
 use crate::*;
 
 pub(super) struct Executor<'store, 'stack> {
@@ -22,22 +23,117 @@ pub(super) struct Executor<'store, 'stack> {
 impl<'store, 'stack> Executor<'store, 'stack> {
     pub(crate) fn new(store: &'store mut Store, stack: &'stack mut Stack) -> Result<Self> {
         let current_frame = stack.call_stack.pop().expect("no call frame, this is a bug");
-        let current_module = store.get_module_instance_raw(current_frame.module_addr());
+        let current_module =
+            store.get_module_instance_raw(current_frame.module_addr()).ok_or(Error::InstanceRemoved)?;
         Ok(Self { cf: current_frame, module: current_module, stack, store })
     }
 
     #[inline]
     pub(crate) fn run_to_completion(&mut self) -> Result<()> {
+        let instrumented =
+            self.store.profiler.is_some() || self.store.coverage.is_some() || self.store.call_stats.is_some();
+        let res = match instrumented {
+            true => self.run_loop::<true>(),
+            false => self.run_loop::<false>(),
+        };
+
+        match res {
+            Some(e) => {
+                self.store.last_backtrace = Some(self.capture_backtrace());
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // instrumentation is only checked when enabled, so the default loop stays as fast as possible
+    #[inline(always)]
+    fn run_loop<const INSTRUMENTED: bool>(&mut self) -> Option<Error> {
         loop {
+            if INSTRUMENTED {
+                self.record_coverage();
+                self.record_stats();
+            }
+
             if let ControlFlow::Break(res) = self.exec_next() {
-                return match res {
-                    Some(e) => Err(e),
-                    None => Ok(()),
-                };
+                return res;
+            }
+
+            if INSTRUMENTED {
+                self.sample_profile();
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn record_stats(&mut self) {
+        let Some(stats) = &mut self.store.call_stats else { return };
+        let depth = self.stack.call_stack.len() + 1;
+        stats.record(self.cf.func_addr(), self.cf.instr_ptr(), self.cf.instructions().len(), depth);
+
+        let height = self.stack.values.height();
+        stats.record_stack_height(ValueCounts {
+            c32: height.s32,
+            c64: height.s64,
+            c128: height.s128,
+            cref: height.sref,
+        });
+    }
+
+    #[inline(always)]
+    fn record_coverage(&mut self) {
+        let Some(coverage) = &mut self.store.coverage else { return };
+        let instr_ptr = self.cf.instr_ptr();
+        let instructions = self.cf.instructions();
+        let func = coverage.function(self.cf.func_addr(), instructions);
+        func.record(instr_ptr);
+
+        // the branch condition is still on top of the stack
+        let outcome = match &instructions[instr_ptr] {
+            Instruction::If(..) | Instruction::IfWithType(..) | Instruction::IfWithFuncType(..) => {
+                (self.stack.values.peek::<i32>() == 0) as usize
             }
+            Instruction::BrIf(_) => (self.stack.values.peek::<i32>() == 0) as usize,
+            Instruction::BrTable(_, len) => (self.stack.values.peek::<i32>() as u32).min(*len) as usize,
+            _ => return,
+        };
+        func.record_branch(instr_ptr, outcome);
+    }
+
+    #[inline(always)]
+    fn sample_profile(&mut self) {
+        let Some(profiler) = &mut self.store.profiler else { return };
+        if profiler.tick() {
+            let callers = self.stack.call_stack.frames().map(CallFrame::func_addr);
+            profiler.record(callers.chain(core::iter::once(self.cf.func_addr())));
         }
     }
 
+    #[cold]
+    fn capture_backtrace(&self) -> Backtrace {
+        let frame = |cf: &CallFrame, instr_ptr: usize| {
+            let module = self.store.get_module_instance_raw(cf.module_addr());
+            let code_offset = cf.func_instance().instruction_offset(instr_ptr);
+            BacktraceFrame {
+                module_addr: cf.module_addr(),
+                func_index: module.as_ref().and_then(|module| {
+                    module.func_addrs().iter().position(|addr| *addr == cf.func_addr()).map(|i| i as u32)
+                }),
+                instr_index: instr_ptr,
+                code_offset,
+                location: code_offset.and_then(|offset| module?.0.line_table.as_ref()?.lookup(offset)),
+            }
+        };
+
+        let mut frames = Vec::new();
+        frames.push(frame(&self.cf, self.cf.instr_ptr()));
+
+        // suspended frames already point to the instruction after the call
+        let callers = self.stack.call_stack.frames().rev();
+        frames.extend(callers.map(|cf| frame(cf, cf.instr_ptr().saturating_sub(1))));
+        Backtrace { frames }
+    }
+
     #[inline(always)]
     fn exec_next(&mut self) -> ControlFlow<Option<Error>> {
         use tinywasm_types::Instruction::*;
@@ -99,7 +195,7 @@ impl<'store, 'stack> Executor<'store, 'stack> {
             I64Const(val) => self.exec_const(*val),
             F32Const(val) => self.exec_const(*val),
             F64Const(val) => self.exec_const(*val),
-            RefFunc(func_idx) => self.exec_const::<ValueRef>(Some(*func_idx)),
+            RefFunc(func_idx) => self.exec_const::<ValueRef>(Some(self.module.resolve_func_addr(*func_idx))),
             RefNull(_) => self.exec_const::<ValueRef>(None),
             RefIsNull => self.exec_ref_is_null(),
 
@@ -316,30 +412,61 @@ impl<'store, 'stack> Executor<'store, 'stack> {
         ControlFlow::Break(Some(Trap::Unreachable.into()))
     }
 
-    fn exec_call(&mut self, wasm_func: Rc<WasmFunction>, owner: ModuleInstanceAddr) -> ControlFlow<Option<Error>> {
+    fn exec_call(
+        &mut self,
+        wasm_func: Rc<WasmFunction>,
+        func_addr: FuncAddr,
+        owner: ModuleInstanceAddr,
+    ) -> ControlFlow<Option<Error>> {
+        self.module.swap_with(owner, self.store).to_cf()?;
         let locals = self.stack.values.pop_locals(wasm_func.params, wasm_func.locals);
-        let new_call_frame = CallFrame::new_raw(wasm_func, owner, locals, self.stack.blocks.len() as u32);
+        let new_call_frame = CallFrame::new_raw(wasm_func, func_addr, owner, locals, self.stack.blocks.len() as u32);
         self.cf.incr_instr_ptr(); // skip the call instruction
         self.stack.call_stack.push(core::mem::replace(&mut self.cf, new_call_frame))?;
-        self.module.swap_with(self.cf.module_addr(), self.store);
+        ControlFlow::Continue(())
+    }
+
+    // call a host function with its params on top of the stack
+    // host functions can collect extern refs, so the references of this code are kept in the store during the call
+    #[inline]
+    fn call_host(&mut self, func: &HostFunction) -> Result<()> {
+        let params = usize::from(func.params.sref);
+        let suspended = self.store.extern_refs.suspend(self.stack, &mut self.cf, params);
+        let ctx = FuncContext { store: self.store, module_addr: self.module.id() };
+        let res = (func.func)(ctx, &mut HostCall::stack(&mut self.stack.values, func.params));
+        if suspended {
+            self.store.extern_refs.resume(self.stack, &mut self.cf, params);
+        }
+        res
+    }
+
+    // instances can only be removed by host functions, so this is checked after every host call
+    #[inline]
+    fn check_removed(&self) -> ControlFlow<Option<Error>> {
+        if unlikely(self.store.is_removed(self.module.id())) {
+            return ControlFlow::Break(Some(Error::InstanceRemoved));
+        }
         ControlFlow::Continue(())
     }
     fn exec_call_direct(&mut self, v: u32) -> ControlFlow<Option<Error>> {
-        let func_inst = self.store.get_func(self.module.resolve_func_addr(v));
+        let func_addr = self.module.resolve_func_addr(v);
+        let func_inst = self.store.get_func(func_addr);
         let wasm_func = match &func_inst.func {
             crate::Function::Wasm(wasm_func) => wasm_func,
             crate::Function::Host(host_func) => {
                 let func = &host_func.clone();
-                let params = self.stack.values.pop_params(&host_func.ty.params);
-                let res =
-                    (func.func)(FuncContext { store: self.store, module_addr: self.module.id() }, &params).to_cf()?;
-                self.stack.values.extend_from_wasmvalues(&res);
+                if let Some(stats) = &mut self.store.call_stats {
+                    stats.record_host_call();
+                }
+
+                self.call_host(func).to_cf()?;
+                self.check_removed()?;
                 self.cf.incr_instr_ptr();
                 return ControlFlow::Continue(());
             }
         };
 
-        self.exec_call(wasm_func.clone(), func_inst.owner)
+        self.exec_call(wasm_func.clone(), func_addr, func_inst.owner)
     }
     fn exec_call_indirect(&mut self, type_addr: u32, table_addr: u32) -> ControlFlow<Option<Error>> {
         // verify that the table is of the right type, this should be validated by the parser already
@@ -369,14 +496,15 @@ impl<'store, 'stack> Executor<'store, 'stack> {
                 }
 
                 let host_func = host_func.clone();
-                let params = self.stack.values.pop_params(&host_func.ty.params);
-                let res =
-                    match (host_func.func)(FuncContext { store: self.store, module_addr: self.module.id() }, &params) {
-                        Ok(res) => res,
-                        Err(e) => return ControlFlow::Break(Some(e)),
-                    };
-
-                self.stack.values.extend_from_wasmvalues(&res);
+                if let Some(stats) = &mut self.store.call_stats {
+                    stats.record_host_call();
+                }
+
+                if let Err(e) = self.call_host(&host_func) {
+                    return ControlFlow::Break(Some(e));
+                }
+
+                self.check_removed()?;
                 self.cf.incr_instr_ptr();
                 return ControlFlow::Continue(());
             }
@@ -388,7 +516,7 @@ impl<'store, 'stack> Executor<'store, 'stack> {
             ));
         }
 
-        self.exec_call(wasm_func.clone(), func_inst.owner)
+        self.exec_call(wasm_func.clone(), func_ref, func_inst.owner)
     }
 
     fn exec_if(&mut self, else_offset: u32, end_offset: u32, (params, results): (StackHeight, StackHeight)) {
@@ -478,7 +606,7 @@ impl<'store, 'stack> Executor<'store, 'stack> {
             self.stack.blocks.truncate(old);
         }
 
-        self.module.swap_with(self.cf.module_addr(), self.store);
+        self.module.swap_with(self.cf.module_addr(), self.store).to_cf()?;
         ControlFlow::Continue(())
     }
     fn exec_end_block(&mut self) {
@@ -520,10 +648,16 @@ impl<'store, 'stack> Executor<'store, 'stack> {
         let mem = self.store.get_mem_mut(self.module.resolve_mem_addr(addr));
         let prev_size = mem.page_count as i32;
         let pages_delta = self.stack.values.pop::<i32>();
-        self.stack.values.push::<i32>(match mem.grow(pages_delta) {
+        let page_size = mem.kind.page_size();
+        let res = match mem.grow(pages_delta) {
             Some(_) => prev_size,
             None => -1,
-        });
+        };
+
+        if let (Some(stats), true) = (&mut self.store.call_stats, res != -1) {
+            stats.record_memory_grow(pages_delta as u64 * page_size);
+        }
+        self.stack.values.push::<i32>(res);
     }
 
     fn exec_memory_copy(&mut self, from: u32, to: u32) -> Result<()> {
@@ -738,7 +872,7 @@ impl<'store, 'stack> Executor<'store, 'stack> {
             return Ok(());
         }
 
-        table.fill(self.module.func_addrs(), i as usize, n as usize, val.into())
+        table.fill(i as usize, n as usize, val.into())
     }
 
     fn exec_local_copy<T: InternalValue>(&mut self, from: u16, to: u16) {
@@ -6,12 +6,14 @@ use crate::Trap;
 use crate::{unlikely, Error};
 
 use alloc::boxed::Box;
-use alloc::{rc::Rc, vec, vec::Vec};
-use tinywasm_types::{Instruction, LocalAddr, ModuleInstanceAddr, WasmFunction, WasmValue};
+use alloc::{vec, vec::Vec};
+
+use crate::Rc;
+use tinywasm_types::{FuncAddr, Instruction, LocalAddr, ModuleInstanceAddr, WasmFunction, WasmValue};
 
 pub(crate) const MAX_CALL_STACK_SIZE: usize = 1024;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub(crate) struct CallStack {
     stack: Vec<CallFrame>,
 }
@@ -35,12 +37,23 @@ impl CallStack {
         self.stack.push(call_frame);
         ControlFlow::Continue(())
     }
+
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// iterate over the suspended frames, outermost first
+    pub(crate) fn frames(&self) -> impl DoubleEndedIterator<Item = &CallFrame> {
+        self.stack.iter()
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct CallFrame {
     instr_ptr: usize,
     func_instance: Rc<WasmFunction>,
+    func_addr: FuncAddr,
     block_ptr: u32,
     module_addr: ModuleInstanceAddr,
     pub(crate) locals: Locals,
@@ -80,6 +93,16 @@ impl CallFrame {
         self.instr_ptr += offset;
     }
 
+    #[inline(always)]
+    pub(crate) fn func_addr(&self) -> FuncAddr {
+        self.func_addr
+    }
+
+    #[inline(always)]
+    pub(crate) fn func_instance(&self) -> &WasmFunction {
+        &self.func_instance
+    }
+
     #[inline(always)]
     pub(crate) fn module_addr(&self) -> ModuleInstanceAddr {
         self.module_addr
@@ -143,6 +166,7 @@ impl CallFrame {
     #[inline(always)]
     pub(crate) fn new(
         wasm_func_inst: Rc<WasmFunction>,
+        func_addr: FuncAddr,
         owner: ModuleInstanceAddr,
         params: &[WasmValue],
         block_ptr: u32,
@@ -179,17 +203,18 @@ impl CallFrame {
             }
         };
 
-        Self { instr_ptr: 0, func_instance: wasm_func_inst, module_addr: owner, block_ptr, locals }
+        Self { instr_ptr: 0, func_instance: wasm_func_inst, func_addr, module_addr: owner, block_ptr, locals }
     }
 
     #[inline]
     pub(crate) fn new_raw(
         wasm_func_inst: Rc<WasmFunction>,
+        func_addr: FuncAddr,
         owner: ModuleInstanceAddr,
         locals: Locals,
         block_ptr: u32,
     ) -> Self {
-        Self { instr_ptr: 0, func_instance: wasm_func_inst, module_addr: owner, block_ptr, locals }
+        Self { instr_ptr: 0, func_instance: wasm_func_inst, func_addr, module_addr: owner, block_ptr, locals }
     }
 
     #[inline(always)]
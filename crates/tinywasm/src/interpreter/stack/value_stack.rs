@@ -91,11 +91,8 @@ impl ValueStack {
         T::replace_top(self, func)
     }
 
-    pub(crate) fn pop_params(&mut self, val_types: &[ValType]) -> Vec<WasmValue> {
-        val_types.iter().map(|val_type| self.pop_wasmvalue(*val_type)).collect::<Vec<_>>()
-    }
-
-    pub(crate) fn pop_results(&mut self, val_types: &[ValType]) -> Vec<WasmValue> {
+    // pop values of the given types, the last type being on top of the stack
+    pub(crate) fn pop_values(&mut self, val_types: &[ValType]) -> Vec<WasmValue> {
         let mut results = val_types.iter().rev().map(|val_type| self.pop_wasmvalue(*val_type)).collect::<Vec<_>>();
         results.reverse();
         results
@@ -22,6 +22,11 @@
 //!  Enables the `tinywasm-parser` crate. This is enabled by default.
 //!- **`archive`**\
 //!  Enables pre-parsing of archives. This is enabled by default.
+//!- **`dwarf`**\
+//!  Enables reading DWARF line information, so [`Backtrace`]s include guest source locations.
+//!- **`sync`**\
+//!  Uses `Arc` instead of `Rc`, so [`Store`] is `Send` and [`Module`] and [`ModuleInstance`] are `Send` and `Sync`.
+//!  Host functions have to be `Send` and `Sync` as well.
 //!
 //! With all these features disabled, `TinyWasm` only depends on `core`, `alloc` and `libm`.
 //! By disabling `std`, you can use `TinyWasm` in `no_std` environments. This requires
@@ -92,21 +97,39 @@ pub(crate) mod log {
 }
 
 mod error;
+pub use backtrace::{Backtrace, BacktraceFrame};
+pub use coverage::{Coverage, CoverageBitmap, FunctionCoverage, ModuleCoverage};
 pub use error::*;
-pub use func::{FuncHandle, FuncHandleTyped};
+pub use func::{FuncHandle, FuncHandleTyped, FuncRef, WasmType};
+pub use handles::{Global, Memory, Table};
+pub use host_func::{HostFuncResult, IntoHostFunc};
 pub use imports::*;
 pub use instance::ModuleInstance;
-pub use module::Module;
+pub use linker::{InstancePre, Linker};
+pub use module::{ExportType, ExternType, ImportType, Module};
+pub use profiler::{Profile, ProfileSample};
 pub use reference::*;
+pub use stats::CallStats;
 pub use store::*;
 
+mod backtrace;
+mod coverage;
 mod func;
+mod handles;
+mod host_func;
 mod imports;
 mod instance;
+mod linker;
 mod module;
+mod preinit;
+mod profiler;
 mod reference;
+mod stats;
 mod store;
 
+#[cfg(all(test, feature = "parser"))]
+mod test_util;
+
 /// Runtime for executing WebAssembly modules.
 pub mod interpreter;
 pub use interpreter::InterpreterRuntime;
@@ -122,6 +145,23 @@ pub mod types {
     pub use tinywasm_types::*;
 }
 
+// reference counting used for shared data, atomic with the `sync` feature
+#[cfg(not(feature = "sync"))]
+pub(crate) use alloc::rc::Rc;
+#[cfg(feature = "sync")]
+pub(crate) use alloc::sync::Arc as Rc;
+
+#[cfg(feature = "sync")]
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send::<Store>();
+    assert_send_sync::<Module>();
+    assert_send_sync::<ModuleInstance>();
+    assert_send_sync::<Imports>();
+    assert_send_sync::<InstancePre>();
+};
+
 #[cold]
 pub(crate) fn cold() {}
 
@@ -0,0 +1,674 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use tinywasm_types::{Export, ExternVal, ExternalKind, Import, ImportKind, ModuleInstanceAddr, WasmFunction};
+
+use crate::{Extern, ExternType, Imports, LinkingError, Module, ModuleInstance, Rc, ResolvedImports, Result, Store};
+
+// maximum number of aliases followed when resolving a name, to detect cycles
+const MAX_ALIAS_DEPTH: usize = 32;
+
+/// Links modules, instances and host definitions by name
+///
+/// Imports are resolved in the following order:
+/// 1. Host definitions added using [`Linker::define`]
+/// 2. Aliases added using [`Linker::alias`] and [`Linker::alias_module`]
+/// 3. Exports of instances added using [`Linker::define_instance`]
+/// 4. Exports of modules added using [`Linker::define_module`], which are instantiated automatically
+///    the first time they are needed in a store
+///
+/// Defining the same name twice is an error, unless shadowing is enabled using [`Linker::allow_shadowing`].
+/// Imports that can't be resolved this way fail with [`LinkingError::UnknownImports`], the linker doesn't
+/// use an [`ImportResolver`](crate::ImportResolver), which is only consulted by [`Imports`].
+///
+/// ## Example
+/// ```rust
+/// # fn main() -> tinywasm::Result<()> {
+/// use tinywasm::{Extern, Linker};
+/// use tinywasm::types::WasmValue;
+///
+/// let mut linker = Linker::new();
+/// linker
+///     .define("env", "print_i32", Extern::typed_func(|_ctx: tinywasm::FuncContext<'_>, _arg: i32| Ok(())))?
+///     .define("env", "offset", Extern::global(WasmValue::I32(0), false))?
+///     .alias("env", "print_i32", "wasi", "print")?;
+///
+/// // modules can now be instantiated using `linker.instantiate(&mut store, module)`
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Linker {
+    allow_shadowing: bool,
+    definitions: BTreeMap<(String, String), Extern>,
+    aliases: BTreeMap<(String, String), (String, String)>,
+    module_aliases: BTreeMap<String, String>,
+    modules: BTreeMap<String, Module>,
+    instances: BTreeMap<(usize, String), ModuleInstanceAddr>, // by store id and name
+}
+
+// the result of resolving an import name
+enum Resolved<'a> {
+    Extern(&'a Extern),
+    Instance(ModuleInstance, &'a str),    // an export of an instance in the store
+    Module(&'a str, &'a Module, &'a str), // an export of a module that has not been instantiated in the store yet
+}
+
+impl Linker {
+    /// Create a new empty linker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow definitions to replace existing definitions with the same name
+    ///
+    /// A new definition replaces all earlier definitions of the name, including aliases.
+    /// Replacing a module name also drops the instances of the earlier module in all stores,
+    /// so the new module is instantiated the next time it is needed.
+    pub fn allow_shadowing(&mut self, allow: bool) -> &mut Self {
+        self.allow_shadowing = allow;
+        self
+    }
+
+    /// Define a host value
+    pub fn define(&mut self, module: &str, name: &str, value: Extern) -> Result<&mut Self> {
+        self.shadow_definition(module, name)?;
+        self.definitions.insert((module.to_string(), name.to_string()), value);
+        Ok(self)
+    }
+
+    /// Make the definition `module.name` also available as `as_module.as_name`
+    ///
+    /// Aliases are resolved when a module is instantiated, so the definition doesn't need to exist yet.
+    pub fn alias(&mut self, module: &str, name: &str, as_module: &str, as_name: &str) -> Result<&mut Self> {
+        self.shadow_definition(as_module, as_name)?;
+        self.aliases.insert((as_module.to_string(), as_name.to_string()), (module.to_string(), name.to_string()));
+        Ok(self)
+    }
+
+    /// Make all definitions of `module` also available under the name `as_module`
+    pub fn alias_module(&mut self, module: &str, as_module: &str) -> Result<&mut Self> {
+        self.shadow_module(as_module, None)?;
+        self.module_aliases.insert(as_module.to_string(), module.to_string());
+        Ok(self)
+    }
+
+    /// Make the exports of an instance available under the module name `name`
+    ///
+    /// The definition is only used when instantiating modules in the store of the instance.
+    pub fn define_instance(&mut self, name: &str, instance: &ModuleInstance) -> Result<&mut Self> {
+        self.shadow_module(name, Some(instance.0.store_id))?;
+        self.instances.insert((instance.0.store_id, name.to_string()), instance.id());
+        Ok(self)
+    }
+
+    /// Make the exports of a module available under the module name `name`
+    ///
+    /// The module is instantiated once per store, the first time one of its exports is imported.
+    pub fn define_module(&mut self, name: &str, module: Module) -> Result<&mut Self> {
+        self.shadow_module(name, None)?;
+        self.modules.insert(name.to_string(), module);
+        Ok(self)
+    }
+
+    /// Instantiate a module, resolving its imports using the linker
+    ///
+    /// Modules added using [`Linker::define_module`] are instantiated first if they are needed.
+    /// If imports can't be resolved, [`LinkingError::UnknownImports`] lists all of them.
+    /// Runs the start function if it exists.
+    pub fn instantiate(&mut self, store: &mut Store, module: Module) -> Result<ModuleInstance> {
+        self.instantiate_inner(store, module, &mut Vec::new())
+    }
+
+    /// Get the instance of a module added using [`Linker::define_module`], instantiating it if needed
+    pub fn module_instance(&mut self, store: &mut Store, name: &str) -> Result<ModuleInstance> {
+        if let Some(instance) = self.instance(store, name) {
+            return Ok(instance);
+        }
+
+        let Some(module) = self.modules.get(name).cloned() else {
+            return Err(LinkingError::UnknownImports { imports: alloc::vec![(name.to_string(), String::new())] }.into());
+        };
+
+        let instance = self.instantiate_inner(store, module, &mut alloc::vec![name.to_string()])?;
+        self.instances.insert((store.id(), name.to_string()), instance.id());
+        Ok(instance)
+    }
+
+    /// Resolve the imports of a module into an [`Imports`] object
+    ///
+    /// Modules added using [`Linker::define_module`] are instantiated if they are needed.
+    pub fn imports(&mut self, store: &mut Store, module: &Module) -> Result<Imports> {
+        self.resolve_imports(store, module, &mut Vec::new())
+    }
+
+    /// Resolve and type check the imports of a module once, so it can be instantiated many times
+    ///
+    /// Imports are resolved using host definitions, aliases and modules added using [`Linker::define_module`].
+    /// Instances added using [`Linker::define_instance`] belong to a single store and are not used.
+    /// If imports can't be resolved, [`LinkingError::UnknownImports`] lists all of them.
+    pub fn instantiate_pre(&self, module: &Module) -> Result<InstancePre> {
+        let mut templates = Vec::new();
+        self.pre_template(module, &mut BTreeMap::new(), &mut Vec::new(), &mut templates)?;
+        Ok(InstancePre { templates: templates.into() })
+    }
+
+    fn instantiate_inner(
+        &mut self,
+        store: &mut Store,
+        module: Module,
+        stack: &mut Vec<String>,
+    ) -> Result<ModuleInstance> {
+        let imports = self.resolve_imports(store, &module, stack)?;
+        module.instantiate(store, Some(imports))
+    }
+
+    fn resolve_imports(&mut self, store: &mut Store, module: &Module, stack: &mut Vec<String>) -> Result<Imports> {
+        let mut unresolved = Vec::new();
+        let mut dependencies = BTreeSet::new();
+        for import in module.0.imports.iter() {
+            match self.resolve(Some(store), &import.module, &import.name) {
+                Some(Resolved::Extern(_)) => {}
+                Some(Resolved::Instance(instance, name)) if instance.export_addr(name).is_some() => {}
+                Some(Resolved::Module(module_name, module, name))
+                    if module.0.exports.iter().any(|e| &*e.name == name) =>
+                {
+                    dependencies.insert(module_name.to_string());
+                }
+                _ => unresolved.push((import.module.to_string(), import.name.to_string())),
+            }
+        }
+
+        if !unresolved.is_empty() {
+            return Err(LinkingError::UnknownImports { imports: unresolved }.into());
+        }
+
+        for name in dependencies {
+            if stack.contains(&name) {
+                return Err(LinkingError::CyclicDependency { module: name }.into());
+            }
+
+            stack.push(name.clone());
+            let instance = self.instantiate_inner(store, self.modules[&name].clone(), stack)?;
+            stack.pop();
+            self.instances.insert((store.id(), name), instance.id());
+        }
+
+        // all dependencies are instantiated now, so everything resolves to a host value or an instance export
+        let mut imports = Imports::new();
+        for import in module.0.imports.iter() {
+            match self.resolve(Some(store), &import.module, &import.name) {
+                Some(Resolved::Extern(value)) => {
+                    imports.define(&import.module, &import.name, value.clone())?;
+                }
+                Some(Resolved::Instance(instance, name)) => {
+                    let value = instance.export_addr(name).ok_or_else(|| LinkingError::unknown_import(import))?;
+                    imports.define_link(&import.module, &import.name, value);
+                }
+                _ => return Err(LinkingError::unknown_import(import).into()),
+            }
+        }
+
+        Ok(imports)
+    }
+
+    // instances are only used if a store is given
+    // add the templates of a module and its dependencies, returns the index of the module's template
+    fn pre_template(
+        &self,
+        module: &Module,
+        indices: &mut BTreeMap<String, usize>,
+        stack: &mut Vec<String>,
+        templates: &mut Vec<Template>,
+    ) -> Result<usize> {
+        let mut unresolved = Vec::new();
+        let mut resolved = Vec::with_capacity(module.0.imports.len());
+        for import in module.0.imports.iter() {
+            match self.resolve(None, &import.module, &import.name) {
+                Some(Resolved::Extern(value)) => resolved.push((import, Resolved::Extern(value))),
+                Some(Resolved::Module(module_name, dependency, name)) if export(dependency, name).is_some() => {
+                    resolved.push((import, Resolved::Module(module_name, dependency, name)))
+                }
+                _ => unresolved.push((import.module.to_string(), import.name.to_string())),
+            }
+        }
+
+        if !unresolved.is_empty() {
+            return Err(LinkingError::UnknownImports { imports: unresolved }.into());
+        }
+
+        let mut imports = Vec::with_capacity(resolved.len());
+        for (import, value) in resolved {
+            match value {
+                Resolved::Extern(value) => {
+                    Imports::check_extern(module, import, value)?;
+                    imports.push(PreImport::Extern(value.clone()));
+                }
+                Resolved::Module(module_name, dependency, name) => {
+                    let export = export(dependency, name).ok_or_else(|| LinkingError::unknown_import(import))?;
+                    check_export(module, import, dependency, export)?;
+
+                    let idx = match indices.get(module_name) {
+                        Some(idx) => *idx,
+                        None if stack.iter().any(|name| name == module_name) => {
+                            return Err(LinkingError::CyclicDependency { module: module_name.to_string() }.into());
+                        }
+                        None => {
+                            stack.push(module_name.to_string());
+                            let idx = self.pre_template(dependency, indices, stack, templates)?;
+                            stack.pop();
+                            indices.insert(module_name.to_string(), idx);
+                            idx
+                        }
+                    };
+
+                    imports.push(PreImport::Export(idx, export.kind, export.index));
+                }
+                Resolved::Instance(..) => return Err(LinkingError::unknown_import(import).into()),
+            }
+        }
+
+        let mut module = module.clone();
+        let funcs = core::mem::take(&mut module.0.funcs).into_vec().into_iter().map(Rc::new).collect();
+        templates.push(Template { module, funcs, imports });
+        Ok(templates.len() - 1)
+    }
+
+    fn resolve<'a>(&'a self, store: Option<&Store>, mut module: &'a str, mut name: &'a str) -> Option<Resolved<'a>> {
+        for _ in 0..MAX_ALIAS_DEPTH {
+            let key = (module.to_string(), name.to_string());
+            if let Some(value) = self.definitions.get(&key) {
+                return Some(Resolved::Extern(value));
+            }
+
+            if let Some((alias_module, alias_name)) = self.aliases.get(&key) {
+                (module, name) = (alias_module, alias_name);
+                continue;
+            }
+
+            if let Some(alias_module) = self.module_aliases.get(module) {
+                module = alias_module;
+                continue;
+            }
+
+            if let Some(instance) = store.and_then(|store| self.instance(store, module)) {
+                return Some(Resolved::Instance(instance, name));
+            }
+
+            let (module_name, module) = self.modules.get_key_value(module)?;
+            return Some(Resolved::Module(module_name, module, name));
+        }
+
+        None
+    }
+
+    // get an instance registered under `name` in the store, if it wasn't removed
+    fn instance(&self, store: &Store, name: &str) -> Option<ModuleInstance> {
+        let addr = self.instances.get(&(store.id(), name.to_string()))?;
+        store.get_module_instance(*addr).cloned()
+    }
+
+    // make room for a new definition, removing the existing ones if shadowing is allowed
+    fn shadow_definition(&mut self, module: &str, name: &str) -> Result<()> {
+        let key = (module.to_string(), name.to_string());
+        if !self.allow_shadowing && (self.definitions.contains_key(&key) || self.aliases.contains_key(&key)) {
+            return Err(LinkingError::DuplicateDefinition { module: key.0, name: key.1 }.into());
+        }
+
+        self.definitions.remove(&key);
+        self.aliases.remove(&key);
+        Ok(())
+    }
+
+    // make room for a new module name, instances of a shadowed module are removed from all stores
+    fn shadow_module(&mut self, name: &str, store_id: Option<usize>) -> Result<()> {
+        let instance_exists = store_id.is_some_and(|id| self.instances.contains_key(&(id, name.to_string())));
+        if !self.allow_shadowing
+            && (instance_exists || self.modules.contains_key(name) || self.module_aliases.contains_key(name))
+        {
+            return Err(LinkingError::DuplicateDefinition { module: name.to_string(), name: String::new() }.into());
+        }
+
+        self.module_aliases.remove(name);
+        self.modules.remove(name);
+        self.instances.retain(|(_, instance_name), _| instance_name != name);
+        Ok(())
+    }
+}
+
+/// A module with imports that have been resolved and type checked by a [`Linker`]
+///
+/// Created using [`Linker::instantiate_pre`]. Instantiating skips name resolution and type checks,
+/// so many instances of the same module can be created cheaply, e.g. in a new store for every request.
+/// The module's functions are shared between all instances, and cloning an `InstancePre` is cheap.
+#[derive(Debug, Clone)]
+pub struct InstancePre {
+    templates: Rc<[Template]>, // dependencies in instantiation order, followed by the module itself
+}
+
+#[derive(Debug)]
+struct Template {
+    module: Module, // without its functions, these are stored in `funcs`
+    funcs: Vec<Rc<WasmFunction>>,
+    imports: Vec<PreImport>,
+}
+
+#[derive(Debug, Clone)]
+enum PreImport {
+    Extern(Extern),
+    Export(usize, ExternalKind, u32), // an export of the dependency with the given template index
+}
+
+impl InstancePre {
+    /// Instantiate the module in the given store
+    ///
+    /// Modules added using [`Linker::define_module`] that the module depends on are instantiated as well,
+    /// once for every call. Unlike [`Linker::instantiate`], which shares these instances between all modules
+    /// of a store, every instance gets its own dependencies.
+    /// Runs the start functions if they exist. If instantiation fails, the instances created by this call
+    /// are removed from the store again.
+    pub fn instantiate(&self, store: &mut Store) -> Result<ModuleInstance> {
+        let first = store.next_module_instance_idx();
+        let res = self.instantiate_templates(store);
+        if res.is_err() {
+            for addr in first..store.next_module_instance_idx() {
+                let _ = store.remove_instance(addr);
+            }
+        }
+        res
+    }
+
+    fn instantiate_templates(&self, store: &mut Store) -> Result<ModuleInstance> {
+        let mut instances: Vec<ModuleInstance> = Vec::with_capacity(self.templates.len());
+        for template in self.templates.iter() {
+            let idx = store.next_module_instance_idx();
+            let mut addrs = ResolvedImports::new();
+            for (import, value) in template.module.0.imports.iter().zip(template.imports.iter()) {
+                match value {
+                    PreImport::Extern(value) => addrs.push_extern(store, &template.module, import, value, idx)?,
+                    PreImport::Export(dependency, kind, index) => {
+                        let val = instances[*dependency]
+                            .extern_addr(*kind, *index)
+                            .ok_or_else(|| LinkingError::unknown_import(import))?;
+
+                        match val {
+                            ExternVal::Func(addr) => addrs.funcs.push(addr),
+                            ExternVal::Global(addr) => addrs.globals.push(addr),
+                            // tables and memories can grow, so their current size has to be checked
+                            _ => Imports::link_store(store, &template.module, import, val, &mut addrs)?,
+                        }
+                    }
+                }
+            }
+
+            let funcs = template.funcs.iter().cloned();
+            let instance = ModuleInstance::instantiate_resolved(store, &template.module, funcs, addrs)?;
+            let _ = instance.start(store)?;
+            store.capture_instance_state(&instance);
+            instances.push(instance);
+        }
+
+        Ok(instances.pop().expect("an InstancePre always contains the module itself"))
+    }
+}
+
+fn export<'a>(module: &'a Module, name: &str) -> Option<&'a Export> {
+    module.0.exports.iter().find(|e| &*e.name == name)
+}
+
+// check the type of an export of a dependency, tables and memories are checked on instantiation
+fn check_export(module: &Module, import: &Import, dependency: &Module, export: &Export) -> Result<()> {
+    match (&import.kind, dependency.extern_type(export.kind, export.index)) {
+        (ImportKind::Function(ty), Some(ExternType::Func(actual))) => match module.0.func_types.get(*ty as usize) {
+            Some(expected) => Imports::compare_types(import, &actual, expected),
+            None => Err(LinkingError::incompatible_import_type(import).into()),
+        },
+        (ImportKind::Global(ty), Some(ExternType::Global(actual))) => Imports::compare_types(import, &actual, ty),
+        (ImportKind::Table(_), Some(ExternType::Table(_))) | (ImportKind::Memory(_), Some(ExternType::Memory(_))) => {
+            Ok(())
+        }
+        _ => Err(LinkingError::incompatible_import_type(import).into()),
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod linker_tests {
+    use super::*;
+    use crate::test_util::module;
+    use crate::{Error, FuncContext};
+    use alloc::{format, vec};
+    use tinywasm_types::WasmValue;
+
+    fn constant(value: i32) -> Extern {
+        Extern::typed_func(move |_: FuncContext<'_>, _: ()| Ok(value))
+    }
+
+    fn call(store: &mut Store, instance: &ModuleInstance, name: &str) -> i32 {
+        instance.exported_func::<(), i32>(store, name).unwrap().call(store, ()).unwrap()
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let mut linker = Linker::new();
+        linker.define("env", "f", constant(1)).unwrap();
+        linker.define_module("m", module("(module)")).unwrap();
+
+        let duplicate =
+            |res: Result<&mut Linker>| matches!(res, Err(Error::Linker(LinkingError::DuplicateDefinition { .. })));
+        assert!(duplicate(linker.define("env", "f", constant(2))));
+        assert!(duplicate(linker.alias("other", "g", "env", "f")));
+        assert!(duplicate(linker.define_module("m", module("(module)"))));
+        assert!(duplicate(linker.alias_module("env", "m")));
+
+        linker.allow_shadowing(true);
+        linker.define("env", "f", constant(2)).unwrap();
+
+        let importer = |module_name: &str| {
+            module(&format!(r#"(module (import "{module_name}" "f" (func $f (result i32))) (export "f" (func $f)))"#))
+        };
+        let mut store = Store::default();
+        let instance = linker.instantiate(&mut store, importer("env")).unwrap();
+        assert_eq!(call(&mut store, &instance, "f"), 2);
+    }
+
+    #[test]
+    fn test_shadowing_replaces_other_kinds() {
+        let importer = |module_name: &str| {
+            module(&format!(r#"(module (import "{module_name}" "f" (func $f (result i32))) (export "f" (func $f)))"#))
+        };
+        let exporter =
+            |value: i32| module(&format!(r#"(module (func (export "f") (result i32) (i32.const {value})))"#));
+        let resolve = |linker: &mut Linker, store: &mut Store, module_name: &str| {
+            let instance = linker.instantiate(store, importer(module_name)).unwrap();
+            call(store, &instance, "f")
+        };
+
+        let mut store = Store::default();
+        let mut linker = Linker::new();
+        linker.allow_shadowing(true);
+
+        // a definition replaced by an alias, and the other way around
+        linker.define("env", "f", constant(1)).unwrap().define("env", "g", constant(2)).unwrap();
+        linker.alias("env", "g", "env", "f").unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "env"), 2);
+        linker.define("env", "f", constant(3)).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "env"), 3);
+
+        // a module replaced after it was instantiated in the store
+        linker.define_module("dep", exporter(4)).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 4);
+        linker.define_module("dep", exporter(5)).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 5);
+
+        // a module replaced by a module alias, and the other way around
+        linker.alias_module("env", "dep").unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 3);
+        linker.define_module("dep", exporter(6)).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 6);
+
+        // a module replaced by an instance, and the other way around
+        let instance = exporter(7).instantiate(&mut store, None).unwrap();
+        linker.define_instance("dep", &instance).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 7);
+        linker.define_module("dep", exporter(8)).unwrap();
+        assert_eq!(resolve(&mut linker, &mut store, "dep"), 8);
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut linker = Linker::new();
+
+        // aliases can be added before their target
+        linker.alias("env", "f", "wasi", "f").unwrap();
+        linker.alias_module("env", "host").unwrap();
+        linker.define("env", "f", constant(1)).unwrap();
+        linker.define("env", "g", Extern::global(WasmValue::I32(2), false)).unwrap();
+
+        let mut store = Store::default();
+        let instance = linker
+            .instantiate(
+                &mut store,
+                module(
+                    r#"(module
+                        (import "wasi" "f" (func $f (result i32)))
+                        (import "host" "f" (func $g (result i32)))
+                        (import "host" "g" (global $g i32))
+                        (func (export "sum") (result i32) (i32.add (i32.add (call $f) (call $g)) (global.get $g))))"#,
+                ),
+            )
+            .unwrap();
+        assert_eq!(call(&mut store, &instance, "sum"), 4);
+
+        // aliases that point to each other never resolve
+        linker.alias("b", "x", "a", "x").unwrap().alias("a", "x", "b", "x").unwrap();
+        let res = linker.instantiate(&mut store, module(r#"(module (import "a" "x" (func)))"#));
+        assert!(matches!(res, Err(Error::Linker(LinkingError::UnknownImports { .. }))));
+    }
+
+    #[test]
+    fn test_define_module() {
+        let mut linker = Linker::new();
+        let counter = r#"(module
+            (global $count (mut i32) (i32.const 0))
+            (func (export "inc") (result i32)
+                (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                (global.get $count)))"#;
+        linker.define_module("counter", module(counter)).unwrap();
+
+        // the module is instantiated once per store and shared by all importers
+        let importer = r#"(module (import "counter" "inc" (func $inc (result i32))) (export "inc" (func $inc)))"#;
+        let mut store = Store::default();
+        let a = linker.instantiate(&mut store, module(importer)).unwrap();
+        let b = linker.instantiate(&mut store, module(importer)).unwrap();
+        assert_eq!(call(&mut store, &a, "inc"), 1);
+        assert_eq!(call(&mut store, &b, "inc"), 2);
+
+        let instance = linker.module_instance(&mut store, "counter").unwrap();
+        assert_eq!(call(&mut store, &instance, "inc"), 3);
+
+        let mut other = Store::default();
+        let c = linker.instantiate(&mut other, module(importer)).unwrap();
+        assert_eq!(call(&mut other, &c, "inc"), 1);
+
+        // instances only resolve imports in their own store
+        let mut linker = Linker::new();
+        linker.define_instance("counter", &instance).unwrap();
+        let d = linker.instantiate(&mut store, module(importer)).unwrap();
+        assert_eq!(call(&mut store, &d, "inc"), 4);
+        assert!(linker.instantiate(&mut other, module(importer)).is_err());
+    }
+
+    #[test]
+    fn test_cyclic_dependency() {
+        let mut linker = Linker::new();
+        linker
+            .define_module("a", module(r#"(module (import "b" "f" (func)) (func (export "g")))"#))
+            .unwrap()
+            .define_module("b", module(r#"(module (import "a" "g" (func)) (func (export "f")))"#))
+            .unwrap();
+
+        let importer = module(r#"(module (import "a" "g" (func)))"#);
+        let cyclic = |res: Result<_, Error>| matches!(res, Err(Error::Linker(LinkingError::CyclicDependency { .. })));
+        assert!(cyclic(linker.instantiate_pre(&importer).map(|_| ())));
+        assert!(cyclic(linker.instantiate(&mut Store::default(), importer).map(|_| ())));
+    }
+
+    #[test]
+    fn test_unknown_imports() {
+        let mut linker = Linker::new();
+        linker.define("env", "f", constant(1)).unwrap();
+        let module = module(
+            r#"(module
+                (import "env" "f" (func (result i32)))
+                (import "env" "missing" (func))
+                (import "other" "memory" (memory 1)))"#,
+        );
+
+        let expected = vec![("env".to_string(), "missing".to_string()), ("other".to_string(), "memory".to_string())];
+        let Err(Error::Linker(LinkingError::UnknownImports { imports })) = linker.instantiate_pre(&module) else {
+            panic!("expected unknown imports");
+        };
+        assert_eq!(imports, expected);
+
+        let Err(Error::Linker(LinkingError::UnknownImports { imports })) =
+            linker.instantiate(&mut Store::default(), module)
+        else {
+            panic!("expected unknown imports");
+        };
+        assert_eq!(imports, expected);
+    }
+
+    #[test]
+    fn test_instance_pre() {
+        let mut linker = Linker::new();
+        linker.define("env", "start", Extern::global(WasmValue::I32(10), false)).unwrap();
+        linker.define_module("dep", module(r#"(module (global (export "g") (mut i32) (i32.const 0)))"#)).unwrap();
+        let pre = linker
+            .instantiate_pre(&module(
+                r#"(module
+                    (import "env" "start" (global $start i32))
+                    (import "dep" "g" (global $dep (mut i32)))
+                    (memory (export "memory") 1)
+                    (global $count (mut i32) (global.get $start))
+                    (func (export "inc") (result i32)
+                        (global.set $dep (i32.add (global.get $dep) (i32.const 1)))
+                        (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                        (i32.store (i32.const 0) (global.get $count))
+                        (global.get $count)))"#,
+            ))
+            .unwrap();
+
+        // each instance gets its own memory, globals and dependencies
+        let mut store = Store::default();
+        let a = pre.instantiate(&mut store).unwrap();
+        let b = pre.clone().instantiate(&mut store).unwrap();
+        assert_ne!(a.id(), b.id());
+        assert_eq!(call(&mut store, &a, "inc"), 11);
+        assert_eq!(call(&mut store, &a, "inc"), 12);
+        assert_eq!(call(&mut store, &b, "inc"), 11);
+
+        let memory = |instance: &ModuleInstance| store.data.memories[instance.0.mem_addrs[0] as usize].load(0, 4);
+        assert_eq!(memory(&a).unwrap(), 12i32.to_le_bytes());
+        assert_eq!(memory(&b).unwrap(), 11i32.to_le_bytes());
+        assert_eq!(store.data.funcs[a.0.func_addrs[0] as usize].owner, a.id());
+        assert_eq!(store.next_module_instance_idx(), 4);
+    }
+
+    #[test]
+    fn test_instance_pre_error() {
+        let mut linker = Linker::new();
+        linker.define_module("dep", module(r#"(module (global (export "g") i32 (i32.const 1)))"#)).unwrap();
+        let pre = linker
+            .instantiate_pre(&module(
+                r#"(module (import "dep" "g" (global i32)) (func $start unreachable) (start $start))"#,
+            ))
+            .unwrap();
+
+        // the dependency created for the failed instance is removed as well
+        let mut store = Store::default();
+        assert!(matches!(pre.instantiate(&mut store), Err(Error::Trap(crate::Trap::Unreachable))));
+        assert_eq!(store.next_module_instance_idx(), 2);
+        assert!((0..2).all(|addr| store.get_module_instance(addr).is_none()));
+        assert_eq!(store.collect_garbage(), 0);
+    }
+}
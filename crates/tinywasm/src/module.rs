@@ -1,5 +1,5 @@
 use crate::{Imports, ModuleInstance, Result, Store};
-use tinywasm_types::TinyWasmModule;
+use tinywasm_types::{ExternalKind, FuncType, GlobalType, Import, ImportKind, MemoryType, TableType, TinyWasmModule};
 
 /// A WebAssembly Module
 ///
@@ -53,6 +53,172 @@ impl Module {
     pub fn instantiate(self, store: &mut Store, imports: Option<Imports>) -> Result<ModuleInstance> {
         let instance = ModuleInstance::instantiate(store, self, imports)?;
         let _ = instance.start(store)?;
+        store.capture_instance_state(&instance);
         Ok(instance)
     }
+
+    /// Get the imports of the module and their types
+    pub fn imports(&self) -> impl Iterator<Item = ImportType<'_>> {
+        self.0.imports.iter().filter_map(|import| {
+            Some(ImportType { module: &import.module, name: &import.name, ty: self.import_type(import)? })
+        })
+    }
+
+    // get the type of an import, with function types resolved
+    pub(crate) fn import_type(&self, import: &Import) -> Option<ExternType> {
+        Some(match &import.kind {
+            ImportKind::Function(ty) => ExternType::Func(self.0.func_types.get(*ty as usize)?.clone()),
+            ImportKind::Table(ty) => ExternType::Table(ty.clone()),
+            ImportKind::Memory(ty) => ExternType::Memory(*ty),
+            ImportKind::Global(ty) => ExternType::Global(*ty),
+        })
+    }
+
+    /// Get the exports of the module and their types
+    pub fn exports(&self) -> impl Iterator<Item = ExportType<'_>> {
+        self.0.exports.iter().filter_map(|export| {
+            Some(ExportType { name: &export.name, ty: self.extern_type(export.kind, export.index)? })
+        })
+    }
+
+    // get the type of an item by its index in the module, imported items come first in each index space
+    pub(crate) fn extern_type(&self, kind: ExternalKind, index: u32) -> Option<ExternType> {
+        let imported_count = self.0.imports.iter().filter(|i| ExternalKind::from(&i.kind) == kind).count();
+        if (index as usize) < imported_count {
+            return self.imports().filter(|i| i.ty.kind() == kind).nth(index as usize).map(|i| i.ty);
+        }
+
+        let index = index as usize - imported_count;
+        Some(match kind {
+            ExternalKind::Func => ExternType::Func(self.0.funcs.get(index)?.ty.clone()),
+            ExternalKind::Table => ExternType::Table(self.0.table_types.get(index)?.clone()),
+            ExternalKind::Memory => ExternType::Memory(*self.0.memory_types.get(index)?),
+            ExternalKind::Global => ExternType::Global(self.0.globals.get(index)?.ty),
+        })
+    }
+}
+
+/// The type of an import or export
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ExternType {
+    /// A function
+    Func(FuncType),
+    /// A table
+    Table(TableType),
+    /// A memory
+    Memory(MemoryType),
+    /// A global
+    Global(GlobalType),
+}
+
+impl ExternType {
+    /// Get the kind of the import or export
+    pub fn kind(&self) -> ExternalKind {
+        match self {
+            Self::Func(_) => ExternalKind::Func,
+            Self::Table(_) => ExternalKind::Table,
+            Self::Memory(_) => ExternalKind::Memory,
+            Self::Global(_) => ExternalKind::Global,
+        }
+    }
+}
+
+/// An import of a module, see [`Module::imports`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportType<'a> {
+    /// The module name
+    pub module: &'a str,
+    /// The import name
+    pub name: &'a str,
+    /// The type of the imported value
+    pub ty: ExternType,
+}
+
+/// An export of a module, see [`Module::exports`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportType<'a> {
+    /// The export name
+    pub name: &'a str,
+    /// The type of the exported value
+    pub ty: ExternType,
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod module_tests {
+    use super::*;
+    use crate::test_util::module;
+    use crate::{Extern, Imports, Store};
+    use alloc::vec::Vec;
+    use tinywasm_types::{MemoryArch, ValType, WasmValue};
+
+    const WAT: &str = r#"(module
+        (import "env" "f" (func $f (param i32) (result i64)))
+        (import "env" "g" (global $g i32))
+        (table (export "table") 2 funcref)
+        (memory (export "memory") 1 2)
+        (global (export "counter") (mut i64) (i64.const 0))
+        (func (export "f") (param i32) (result i64) (call $f (local.get 0)))
+        (export "g" (global $g)))"#;
+
+    #[test]
+    fn test_module_types() {
+        let module = module(WAT);
+        let f = FuncType { params: [ValType::I32].into(), results: [ValType::I64].into() };
+        let g = GlobalType { mutable: false, ty: ValType::I32 };
+
+        let imports: Vec<_> = module.imports().collect();
+        assert_eq!(imports.len(), 2);
+        assert_eq!((imports[0].module, imports[0].name, &imports[0].ty), ("env", "f", &ExternType::Func(f.clone())));
+        assert_eq!((imports[1].module, imports[1].name, &imports[1].ty), ("env", "g", &ExternType::Global(g)));
+
+        // imported items come first in each index space
+        let exports: Vec<_> = module.exports().map(|export| (export.name, export.ty)).collect();
+        assert_eq!(
+            exports,
+            [
+                ("table", ExternType::Table(TableType::new(ValType::RefFunc, 2, None))),
+                ("memory", ExternType::Memory(MemoryType::new(MemoryArch::I32, 1, Some(2), None))),
+                ("counter", ExternType::Global(GlobalType { mutable: true, ty: ValType::I64 })),
+                ("f", ExternType::Func(f)),
+                ("g", ExternType::Global(g)),
+            ]
+        );
+        assert_eq!(exports[1].1.kind(), ExternalKind::Memory);
+    }
+
+    #[test]
+    fn test_instance_exports() {
+        let mut imports = Imports::new();
+        imports.define("env", "f", Extern::typed_func(|_: crate::FuncContext<'_>, x: i32| Ok(x as i64 * 2))).unwrap();
+        imports.define("env", "g", Extern::global(WasmValue::I32(3), false)).unwrap();
+
+        let mut store = Store::default();
+        let instance = module(WAT).instantiate(&mut store, Some(imports)).unwrap();
+        let exports: Vec<_> = instance.exports(&store).unwrap().collect();
+        let names: Vec<_> = exports.iter().map(|(name, value)| (*name, value.kind())).collect();
+        assert_eq!(
+            names,
+            [
+                ("table", ExternalKind::Table),
+                ("memory", ExternalKind::Memory),
+                ("counter", ExternalKind::Global),
+                ("f", ExternalKind::Func),
+                ("g", ExternalKind::Global),
+            ]
+        );
+
+        // the handles refer to the instance's items and can be imported by other instances
+        let [(_, Extern::TableHandle(table)), (_, Extern::MemoryHandle(memory)), _, (_, Extern::FuncHandle(f)), (_, Extern::GlobalHandle(g))] =
+            &exports[..]
+        else {
+            panic!("unexpected exports");
+        };
+        let (table, memory, f, g) = (*table, *memory, f.clone(), *g);
+        assert_eq!(table.size(&store).unwrap(), 2);
+        assert_eq!(memory.ty(&store).unwrap().page_count_initial(), 1);
+        assert_eq!(g.get(&store).unwrap(), WasmValue::I32(3));
+        assert_eq!(f.call(&mut store, &[WasmValue::I32(21)]).unwrap(), [WasmValue::I64(42)]);
+        assert!(instance.exports(&Store::default()).is_err());
+    }
 }
@@ -0,0 +1,136 @@
+use alloc::{boxed::Box, format, vec::Vec};
+use tinywasm_types::*;
+
+use crate::interpreter::TinyWasmValue;
+use crate::{Error, Imports, Module, ModuleInstance, Result, Store};
+
+// zero runs shorter than this are included in data segments instead of starting a new segment
+const MAX_DATA_SEGMENT_GAP: usize = 16;
+
+impl Module {
+    /// Pre-initialize a module, similar to [Wizer](https://github.com/bytecodealliance/wizer)
+    ///
+    /// Instantiates the module, runs its start function and the exported `init_func` (e.g. `wizer.initialize`),
+    /// and returns a new module with the resulting memory contents as active data segments and the values of
+    /// mutable globals as their initializers. The start function and the `init_func` export are removed.
+    ///
+    /// Imported memories and globals are not captured, and neither are changes to tables.
+    pub fn preinitialize(&self, imports: Option<Imports>, init_func: &str) -> Result<TinyWasmModule> {
+        let mut store = Store::default();
+        let instance = self.clone().instantiate(&mut store, imports)?;
+        instance.exported_func::<(), ()>(&store, init_func)?.call(&mut store, ())?;
+
+        let mut module = self.0.clone();
+        let imported = |kind: fn(&ImportKind) -> bool| module.imports.iter().filter(|i| kind(&i.kind)).count();
+        let imported_globals = imported(|k| matches!(k, ImportKind::Global(_)));
+        let imported_memories = imported(|k| matches!(k, ImportKind::Memory(_)));
+
+        for (i, global) in module.globals.iter_mut().enumerate() {
+            if global.ty.mutable {
+                let addr = instance.0.global_addrs[imported_globals + i];
+                let value = store.data.globals[addr as usize].value.get();
+                global.init = const_value(&instance, global.ty.ty, value)?;
+            }
+        }
+
+        // active segments were already applied, so they are replaced with empty ones to keep the data indices intact
+        for (data, addr) in module.data.iter_mut().zip(instance.0.data_addrs.iter()) {
+            match data.kind {
+                DataKind::Active { mem, .. } if mem as usize >= imported_memories => {
+                    let arch = module.memory_types[mem as usize - imported_memories].arch();
+                    data.kind = DataKind::Active { mem, offset: const_offset(arch, 0) };
+                    data.data = Box::default();
+                }
+                DataKind::Passive if store.data.datas[*addr as usize].data.is_none() => data.data = Box::default(),
+                _ => {}
+            }
+        }
+
+        let mut data = module.data.into_vec();
+        for (i, ty) in module.memory_types.iter_mut().enumerate() {
+            let mem = (imported_memories + i) as MemAddr;
+            let memory = &store.data.memories[instance.0.mem_addrs[mem as usize] as usize];
+            *ty = ty.with_page_count_initial(memory.page_count as u64);
+
+            for (offset, bytes) in data_segments(&memory.data) {
+                let offset = const_offset(ty.arch(), offset);
+                data.push(Data { data: bytes.into(), range: 0..0, kind: DataKind::Active { mem, offset } });
+            }
+        }
+
+        module.data = data.into_boxed_slice();
+        module.start_func = None;
+        module.exports = module.exports.iter().filter(|e| &*e.name != init_func).cloned().collect();
+        Ok(module)
+    }
+}
+
+// data segment offsets have the index type of their memory
+fn const_offset(arch: MemoryArch, offset: usize) -> ConstInstruction {
+    match arch {
+        MemoryArch::I32 => ConstInstruction::I32Const(offset as i32),
+        MemoryArch::I64 => ConstInstruction::I64Const(offset as i64),
+    }
+}
+
+// convert the value of a global into a constant expression
+fn const_value(instance: &ModuleInstance, ty: ValType, value: TinyWasmValue) -> Result<ConstInstruction> {
+    Ok(match (ty, value) {
+        (ValType::I32, TinyWasmValue::Value32(v)) => ConstInstruction::I32Const(v as i32),
+        (ValType::F32, TinyWasmValue::Value32(v)) => ConstInstruction::F32Const(f32::from_bits(v)),
+        (ValType::I64, TinyWasmValue::Value64(v)) => ConstInstruction::I64Const(v as i64),
+        (ValType::F64, TinyWasmValue::Value64(v)) => ConstInstruction::F64Const(f64::from_bits(v)),
+        (ValType::RefFunc | ValType::RefExtern, TinyWasmValue::ValueRef(None)) => ConstInstruction::RefNull(ty),
+        (ValType::RefFunc, TinyWasmValue::ValueRef(Some(addr))) => {
+            let idx = instance.func_addrs().iter().position(|a| *a == addr).ok_or_else(|| {
+                Error::UnsupportedFeature(format!("function reference {addr} is not part of the module"))
+            })?;
+            ConstInstruction::RefFunc(idx as FuncAddr)
+        }
+        (ty, _) => return Err(Error::UnsupportedFeature(format!("pre-initializing globals of type {ty:?}"))),
+    })
+}
+
+// split memory into the ranges containing non-zero bytes
+pub(crate) fn data_segments(memory: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut segments: Vec<(usize, usize)> = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = memory[pos..].iter().position(|b| *b != 0).map(|p| p + pos) {
+        let end = memory[start..].iter().position(|b| *b == 0).map_or(memory.len(), |p| p + start);
+        match segments.last_mut() {
+            Some((_, last_end)) if start - *last_end < MAX_DATA_SEGMENT_GAP => *last_end = end,
+            _ => segments.push((start, end)),
+        }
+        pos = end;
+    }
+
+    segments.into_iter().map(|(start, end)| (start, &memory[start..end])).collect()
+}
+
+#[cfg(test)]
+mod preinit_tests {
+    use super::*;
+
+    #[test]
+    fn test_data_segments() {
+        let mut memory = alloc::vec![0u8; 256];
+        memory[10] = 1;
+        memory[20] = 2;
+        memory[100..102].copy_from_slice(&[3, 4]);
+        memory[255] = 5;
+
+        let segments = data_segments(&memory);
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0], (10, &memory[10..21]));
+        assert_eq!(segments[1], (100, &[3, 4][..]));
+        assert_eq!(segments[2], (255, &[5][..]));
+        assert!(data_segments(&[0; 16]).is_empty());
+    }
+
+    #[test]
+    fn test_const_offset() {
+        assert_eq!(const_offset(MemoryArch::I32, 16), ConstInstruction::I32Const(16));
+        assert_eq!(const_offset(MemoryArch::I64, 16), ConstInstruction::I64Const(16));
+    }
+}
@@ -0,0 +1,235 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::Write;
+use tinywasm_types::FuncAddr;
+
+/// Samples the WebAssembly call stack every `interval` executed instructions
+///
+/// Counting instructions instead of measuring wall time makes profiles deterministic.
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    interval: u64,
+    remaining: u64,
+    samples: BTreeMap<Box<[FuncAddr]>, u64>,
+    stack: Vec<FuncAddr>,
+}
+
+impl Profiler {
+    pub(crate) fn new(interval: u64) -> Self {
+        let interval = interval.max(1);
+        Self { interval, remaining: interval, samples: BTreeMap::new(), stack: Vec::new() }
+    }
+
+    // count an executed instruction, returns true if a sample should be taken
+    #[inline(always)]
+    pub(crate) fn tick(&mut self) -> bool {
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.remaining = self.interval;
+            return true;
+        }
+        false
+    }
+
+    // record a call stack, outermost function first
+    pub(crate) fn record(&mut self, stack: impl Iterator<Item = FuncAddr>) {
+        self.stack.clear();
+        self.stack.extend(stack);
+        match self.samples.get_mut(self.stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.samples.insert(self.stack.as_slice().into(), 1);
+            }
+        }
+    }
+
+    // aggregate the samples by function name
+    pub(crate) fn finish(self, mut func_name: impl FnMut(FuncAddr) -> String) -> Profile {
+        let mut functions = Vec::new();
+        let mut by_name = BTreeMap::new();
+        let mut by_addr = BTreeMap::new();
+        let mut samples = BTreeMap::<Box<[u32]>, u64>::new();
+
+        for (stack, count) in self.samples {
+            let stack = stack
+                .iter()
+                .map(|addr| {
+                    *by_addr.entry(*addr).or_insert_with(|| {
+                        *by_name.entry(func_name(*addr)).or_insert_with_key(|name: &String| {
+                            functions.push(name.as_str().into());
+                            functions.len() as u32 - 1
+                        })
+                    })
+                })
+                .collect::<Box<[u32]>>();
+            *samples.entry(stack).or_default() += count;
+        }
+
+        let samples = samples.into_iter().map(|(stack, count)| ProfileSample { stack, count }).collect();
+        Profile { interval: self.interval, functions, samples }
+    }
+}
+
+/// A profile recorded by the sampling profiler
+///
+/// See [`crate::Store::start_profiling`]
+#[derive(Debug, Clone)]
+pub struct Profile {
+    interval: u64,
+    functions: Vec<Box<str>>,
+    samples: Vec<ProfileSample>,
+}
+
+/// A call stack and the number of times it was sampled
+#[derive(Debug, Clone)]
+pub struct ProfileSample {
+    /// Indices into [`Profile::functions`], outermost function first
+    pub stack: Box<[u32]>,
+    /// Number of samples taken with this call stack
+    pub count: u64,
+}
+
+impl Profile {
+    /// The number of instructions executed between two samples
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Names of the sampled functions
+    ///
+    /// Names are taken from the `name` section of the module, or `func[<index>]` if not available.
+    pub fn functions(&self) -> &[Box<str>] {
+        &self.functions
+    }
+
+    /// The sampled call stacks
+    pub fn samples(&self) -> &[ProfileSample] {
+        &self.samples
+    }
+
+    /// Render the profile as folded stacks, as used by flamegraph tools like `inferno` or `flamegraph.pl`
+    pub fn to_folded(&self) -> String {
+        let mut out = String::new();
+        for sample in &self.samples {
+            for (i, func) in sample.stack.iter().enumerate() {
+                if i > 0 {
+                    out.push(';');
+                }
+                out.push_str(&self.functions[*func as usize]);
+            }
+            let _ = writeln!(out, " {}", sample.count);
+        }
+        out
+    }
+
+    /// Encode the profile as an uncompressed pprof protobuf message
+    ///
+    /// See <https://github.com/google/pprof/blob/main/proto/profile.proto>
+    pub fn to_pprof(&self) -> Vec<u8> {
+        const STRINGS: [&str; 4] = ["", "samples", "count", "instructions"];
+        let string_id = |func: usize| (STRINGS.len() + func) as u64;
+
+        let mut out = Vec::new();
+        let mut msg = Vec::new();
+
+        // sample_type
+        pprof::varint_field(&mut msg, 1, 1);
+        pprof::varint_field(&mut msg, 2, 2);
+        pprof::bytes_field(&mut out, 1, &msg);
+
+        for sample in &self.samples {
+            // location ids are 1-based function indices, with the leaf first
+            let locations = sample.stack.iter().rev().map(|func| *func as u64 + 1);
+            msg.clear();
+            pprof::packed_field(&mut msg, 1, locations);
+            pprof::packed_field(&mut msg, 2, [sample.count].into_iter());
+            pprof::bytes_field(&mut out, 2, &msg);
+        }
+
+        for id in 1..=self.functions.len() as u64 {
+            let mut line = Vec::new();
+            pprof::varint_field(&mut line, 1, id);
+
+            // location
+            msg.clear();
+            pprof::varint_field(&mut msg, 1, id);
+            pprof::bytes_field(&mut msg, 4, &line);
+            pprof::bytes_field(&mut out, 4, &msg);
+        }
+
+        for i in 0..self.functions.len() {
+            // function
+            msg.clear();
+            pprof::varint_field(&mut msg, 1, i as u64 + 1);
+            pprof::varint_field(&mut msg, 2, string_id(i));
+            pprof::varint_field(&mut msg, 3, string_id(i));
+            pprof::bytes_field(&mut out, 5, &msg);
+        }
+
+        let strings = STRINGS.iter().copied().chain(self.functions.iter().map(|f| &**f));
+        strings.for_each(|s| pprof::bytes_field(&mut out, 6, s.as_bytes()));
+
+        // period_type and period
+        msg.clear();
+        pprof::varint_field(&mut msg, 1, 3);
+        pprof::varint_field(&mut msg, 2, 2);
+        pprof::bytes_field(&mut out, 11, &msg);
+        pprof::varint_field(&mut out, 12, self.interval);
+        out
+    }
+}
+
+// minimal protobuf encoding
+mod pprof {
+    use alloc::vec::Vec;
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    pub(super) fn varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+        varint(out, field << 3);
+        varint(out, value);
+    }
+
+    pub(super) fn bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(out, field << 3 | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    pub(super) fn packed_field(out: &mut Vec<u8>, field: u64, values: impl Iterator<Item = u64>) {
+        let mut packed = Vec::new();
+        values.for_each(|value| varint(&mut packed, value));
+        bytes_field(out, field, &packed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folded() {
+        let mut profiler = Profiler::new(2);
+        for stack in [&[0, 1][..], &[0, 1], &[0, 3], &[0, 2], &[0, 1], &[0, 3]] {
+            if profiler.tick() {
+                profiler.record(stack.iter().copied());
+            }
+        }
+
+        // functions 2 and 3 share a name, so their samples are merged
+        let profile = profiler.finish(|addr| match addr {
+            0 => "main".into(),
+            1 => "a".into(),
+            _ => "b".into(),
+        });
+
+        assert_eq!(profile.to_folded(), "main;a 1\nmain;b 2\n");
+    }
+}
@@ -4,7 +4,9 @@ use alloc::ffi::CString;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-use crate::{MemoryInstance, Result};
+use tinywasm_types::{GlobalType, TableType, WasmValue};
+
+use crate::{DirtyPages, Error, FuncHandle, Global, MemoryInstance, Result, Store, Table};
 
 // This module essentially contains the public APIs to interact with the data stored in the store
 
@@ -40,6 +42,23 @@ impl MemoryRef<'_> {
     pub fn load_vec(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
         self.load(offset, len).map(<[u8]>::to_vec)
     }
+
+    /// Get the indices of all pages written to since dirty page tracking was enabled or last cleared
+    ///
+    /// Pages are [`MemoryRef::dirty_page_size`] bytes large. Empty if dirty page tracking is disabled.
+    /// See [`MemoryRefMut::enable_dirty_tracking`]
+    pub fn dirty_pages(&self) -> Vec<usize> {
+        dirty_pages(self.0)
+    }
+
+    /// Get the size of the pages used for dirty page tracking, or `None` if it is disabled
+    pub fn dirty_page_size(&self) -> Option<usize> {
+        self.0.dirty.as_ref().map(|dirty| dirty.page_size)
+    }
+}
+
+fn dirty_pages(mem: &MemoryInstance) -> Vec<usize> {
+    mem.dirty.as_ref().map(|dirty| dirty.pages().collect()).unwrap_or_default()
 }
 
 impl MemoryRefMut<'_> {
@@ -77,6 +96,107 @@ impl MemoryRefMut<'_> {
     pub fn store(&mut self, offset: usize, len: usize, data: &[u8]) -> Result<()> {
         self.0.store(offset, len, data)
     }
+
+    /// Start recording which pages of `page_size` bytes are written to
+    ///
+    /// The page size is independent of the memory's own page size, smaller pages are more precise
+    /// but take more time to process. If tracking is already enabled, all dirty pages are cleared.
+    /// Memories that track dirty pages are also reset faster by [`crate::ModuleInstance::reset`].
+    pub fn enable_dirty_tracking(&mut self, page_size: usize) {
+        self.0.dirty = Some(DirtyPages::new(page_size));
+    }
+
+    /// Stop recording which pages are written to
+    pub fn disable_dirty_tracking(&mut self) {
+        self.0.dirty = None;
+    }
+
+    /// Get the indices of all pages written to since dirty page tracking was enabled or last cleared
+    ///
+    /// See [`MemoryRef::dirty_pages`]
+    pub fn dirty_pages(&self) -> Vec<usize> {
+        dirty_pages(self.0)
+    }
+
+    /// Get the size of the pages used for dirty page tracking, or `None` if it is disabled
+    pub fn dirty_page_size(&self) -> Option<usize> {
+        self.0.dirty.as_ref().map(|dirty| dirty.page_size)
+    }
+
+    /// Mark all pages as clean
+    pub fn clear_dirty(&mut self) {
+        if let Some(dirty) = &mut self.0.dirty {
+            dirty.clear();
+        }
+    }
+}
+
+/// A reference to a global instance
+#[derive(Debug)]
+pub struct GlobalRef<'a> {
+    pub(crate) store: &'a mut Store,
+    pub(crate) global: Global,
+}
+
+impl GlobalRef<'_> {
+    /// Get the type of the global
+    pub fn ty(&self) -> Result<GlobalType> {
+        self.global.ty(self.store)
+    }
+
+    /// Get the current value of the global, converted to `T`
+    pub fn get<T: TryFrom<WasmValue>>(&self) -> Result<T> {
+        T::try_from(self.global.get(self.store)?).map_err(|_| Error::Other("Type mismatch".to_string()))
+    }
+
+    /// Set the value of the global, which has to be mutable
+    pub fn set<T: Into<WasmValue>>(&mut self, value: T) -> Result<()> {
+        self.global.set(self.store, value.into())
+    }
+}
+
+/// A reference to a table instance
+#[derive(Debug)]
+pub struct TableRef<'a> {
+    pub(crate) store: &'a mut Store,
+    pub(crate) table: Table,
+}
+
+impl TableRef<'_> {
+    /// Get the type of the table
+    pub fn ty(&self) -> Result<TableType> {
+        self.table.ty(self.store)
+    }
+
+    /// Get the current number of elements in the table
+    pub fn size(&self) -> Result<u32> {
+        self.table.size(self.store)
+    }
+
+    /// Get the element at the given index
+    pub fn get(&self, index: u32) -> Result<WasmValue> {
+        self.table.get(self.store, index)
+    }
+
+    /// Get a handle to the function at the given index, or `None` if the element is a null reference
+    pub fn get_func(&self, index: u32) -> Result<Option<FuncHandle>> {
+        self.table.get_func(self.store, index)
+    }
+
+    /// Set the element at the given index
+    pub fn set(&mut self, index: u32, value: WasmValue) -> Result<()> {
+        self.table.set(self.store, index, value)
+    }
+
+    /// Grow the table by `delta` elements set to `init`, returning the previous size
+    pub fn grow(&mut self, delta: u32, init: WasmValue) -> Result<u32> {
+        self.table.grow(self.store, delta, init)
+    }
+
+    /// Set `len` elements starting at `offset` to `value`
+    pub fn fill(&mut self, offset: u32, len: u32, value: WasmValue) -> Result<()> {
+        self.table.fill(self.store, offset, len, value)
+    }
 }
 
 #[doc(hidden)]
@@ -133,3 +253,77 @@ pub trait MemoryStringExt: MemoryRefLoad {
 
 impl MemoryStringExt for MemoryRef<'_> {}
 impl MemoryStringExt for MemoryRefMut<'_> {}
+
+#[cfg(all(test, feature = "parser"))]
+mod reference_tests {
+    use crate::test_util::module;
+    use crate::Store;
+    use tinywasm_types::{ValType, WasmValue};
+
+    #[test]
+    fn test_global_and_table_refs() {
+        let module = module(
+            r#"(module
+                (global (export "counter") (mut i32) (i32.const 5))
+                (global (export "const") i64 (i64.const 1))
+                (table $t (export "table") 1 3 funcref)
+                (func $one (result i32) (i32.const 1))
+                (elem (i32.const 0) $one)
+                (func (export "read") (result i32) (global.get 0))
+                (func (export "size") (result i32) (table.size $t)))"#,
+        );
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, None).unwrap();
+
+        let mut counter = instance.exported_global(&mut store, "counter").unwrap();
+        assert_eq!(counter.get::<i32>().unwrap(), 5);
+        assert!(counter.get::<i64>().is_err());
+        counter.set(7).unwrap();
+        assert!(counter.set(1i64).is_err());
+        assert!(instance.exported_global(&mut store, "const").unwrap().set(2i64).is_err());
+        assert!(instance.exported_table(&mut store, "counter").is_err());
+        assert_eq!(instance.exported_func::<(), i32>(&store, "read").unwrap().call(&mut store, ()).unwrap(), 7);
+
+        let mut table = instance.exported_table(&mut store, "table").unwrap();
+        assert_eq!(table.ty().unwrap().size_max, Some(3));
+        let one = table.get(0).unwrap();
+        assert!(matches!(one, WasmValue::RefFunc(_)));
+
+        assert_eq!(table.grow(2, WasmValue::RefNull(ValType::RefFunc)).unwrap(), 1);
+        assert!(table.grow(1, WasmValue::RefNull(ValType::RefFunc)).is_err());
+        assert!(table.get_func(2).unwrap().is_none());
+        table.fill(1, 2, one).unwrap();
+        assert!(table.fill(2, 2, one).is_err());
+        assert!(table.set(0, WasmValue::I32(0)).is_err());
+        assert!(table.set(3, WasmValue::RefNull(ValType::RefFunc)).is_err());
+        table.set(0, WasmValue::RefNull(ValType::RefFunc)).unwrap();
+        assert_eq!(table.size().unwrap(), 3);
+
+        let func = table.get_func(2).unwrap().unwrap();
+        assert_eq!(func.call(&mut store, &[]).unwrap(), [WasmValue::I32(1)]);
+        assert_eq!(instance.exported_func::<(), i32>(&store, "size").unwrap().call(&mut store, ()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_global_ref_func_refs() {
+        let module = module(
+            r#"(module
+                (global $f (export "f") (mut funcref) (ref.null func))
+                (table 1 funcref)
+                (func $one (export "one") (result i32) (i32.const 1))
+                (func (export "call") (result i32)
+                    (table.set (i32.const 0) (global.get $f))
+                    (call_indirect (result i32) (i32.const 0))))"#,
+        );
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, None).unwrap();
+        let one = instance.exported_func_untyped(&store, "one").unwrap().func_ref();
+
+        // references to functions that don't exist never reach the guest
+        let mut global = instance.exported_global(&mut store, "f").unwrap();
+        assert!(global.set(WasmValue::RefFunc(999)).is_err());
+        assert_eq!(global.get::<WasmValue>().unwrap(), WasmValue::RefNull(ValType::RefFunc));
+        global.set(one).unwrap();
+        assert_eq!(instance.exported_func::<(), i32>(&store, "call").unwrap().call(&mut store, ()).unwrap(), 1);
+    }
+}
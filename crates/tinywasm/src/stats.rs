@@ -0,0 +1,177 @@
+use alloc::collections::BTreeMap;
+use alloc::{boxed::Box, format, vec::Vec};
+use core::mem::{discriminant, Discriminant};
+use tinywasm_types::{FuncAddr, Instruction, ValueCounts};
+
+/// Execution statistics of a function call
+///
+/// See [`crate::FuncHandle::call_with_stats`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallStats {
+    /// Number of executed instructions
+    pub instructions: u64,
+
+    /// Number of executed instructions by [`Instruction`] variant name, e.g. `I32Add`
+    pub instruction_counts: BTreeMap<Box<str>, u64>,
+
+    /// Maximum number of nested WebAssembly call frames
+    pub max_call_depth: usize,
+
+    /// Maximum height of the value stack for each value type
+    pub max_stack_height: ValueCounts,
+
+    /// Number of bytes memories were grown by using `memory.grow`
+    pub memory_grown: u64,
+
+    /// Number of calls to host functions
+    pub host_calls: u64,
+}
+
+/// Collects [`CallStats`] while executing code
+#[derive(Debug, Default)]
+pub(crate) struct StatsRecorder {
+    // execution counts of each instruction, indexed by function address
+    counts: Vec<Vec<u64>>,
+    stats: CallStats,
+}
+
+impl StatsRecorder {
+    #[inline(always)]
+    pub(crate) fn record(&mut self, func_addr: FuncAddr, instr_ptr: usize, func_len: usize, depth: usize) {
+        let func_addr = func_addr as usize;
+        if func_addr >= self.counts.len() {
+            self.counts.resize_with(func_addr + 1, Vec::new);
+        }
+
+        let counts = &mut self.counts[func_addr];
+        if counts.is_empty() {
+            counts.resize(func_len, 0);
+        }
+
+        counts[instr_ptr] += 1;
+        self.stats.instructions += 1;
+        self.stats.max_call_depth = self.stats.max_call_depth.max(depth);
+    }
+
+    #[inline(always)]
+    pub(crate) fn record_stack_height(&mut self, height: ValueCounts) {
+        let max = &mut self.stats.max_stack_height;
+        max.c32 = max.c32.max(height.c32);
+        max.c64 = max.c64.max(height.c64);
+        max.c128 = max.c128.max(height.c128);
+        max.cref = max.cref.max(height.cref);
+    }
+
+    pub(crate) fn record_host_call(&mut self) {
+        self.stats.host_calls += 1;
+    }
+
+    pub(crate) fn record_memory_grow(&mut self, bytes: u64) {
+        self.stats.memory_grown += bytes;
+    }
+
+    // add the statistics of a nested call
+    pub(crate) fn merge(&mut self, other: &Self) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize_with(other.counts.len(), Vec::new);
+        }
+
+        for (counts, other) in self.counts.iter_mut().zip(&other.counts) {
+            if counts.is_empty() {
+                counts.clone_from(other);
+            } else {
+                counts.iter_mut().zip(other).for_each(|(a, b)| *a += b);
+            }
+        }
+
+        let (stats, other) = (&mut self.stats, &other.stats);
+        stats.instructions += other.instructions;
+        stats.max_call_depth = stats.max_call_depth.max(other.max_call_depth);
+        stats.memory_grown += other.memory_grown;
+        stats.host_calls += other.host_calls;
+        self.record_stack_height(other.max_stack_height);
+    }
+
+    // build the instruction histogram, counts are grouped by variant so each name is only formatted once
+    pub(crate) fn finish<'a>(self, mut instructions: impl FnMut(FuncAddr) -> &'a [Instruction]) -> CallStats {
+        let mut stats = self.stats;
+        let mut variants: Vec<(Discriminant<Instruction>, &'a Instruction, u64)> = Vec::new();
+        for (addr, counts) in self.counts.into_iter().enumerate() {
+            if counts.is_empty() {
+                continue;
+            }
+
+            for (instr, count) in instructions(addr as FuncAddr).iter().zip(counts) {
+                if count == 0 {
+                    continue;
+                }
+
+                let variant = discriminant(instr);
+                match variants.iter_mut().find(|(v, _, _)| *v == variant) {
+                    Some((_, _, total)) => *total += count,
+                    None => variants.push((variant, instr, count)),
+                }
+            }
+        }
+
+        for (_, instr, count) in variants {
+            let name = format!("{instr:?}");
+            let name = name.split(|c: char| !c.is_ascii_alphanumeric()).next().unwrap_or_default();
+            stats.instruction_counts.insert(name.into(), count);
+        }
+        stats
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod stats_tests {
+    use crate::test_util::module;
+    use crate::{Extern, FuncContext, Imports, Store};
+
+    #[test]
+    fn test_call_stats() {
+        let module = module(
+            r#"(module
+                (import "env" "host" (func $host))
+                (memory 1)
+                (func $sum (param i32) (result i32)
+                    (local $acc i32)
+                    (block (loop
+                        (br_if 1 (i32.eqz (local.get 0)))
+                        (local.set $acc (i32.add (local.get $acc) (local.get 0)))
+                        (local.set 0 (i32.sub (local.get 0) (i32.const 1)))
+                        (br 0)))
+                    (local.get $acc))
+                (func (export "run") (param i32) (result i32)
+                    (call $host)
+                    (drop (memory.grow (i32.const 2)))
+                    (call $sum (local.get 0))))"#,
+        );
+
+        let mut store = Store::default();
+        let mut imports = Imports::new();
+        imports.define("env", "host", Extern::typed_func(|_: FuncContext<'_>, _: ()| Ok(()))).unwrap();
+        let instance = module.instantiate(&mut store, Some(imports)).unwrap();
+        let run = instance.exported_func::<i32, i32>(&store, "run").unwrap();
+
+        let (result, stats) = run.call_with_stats(&mut store, 3).unwrap();
+        assert_eq!(result, 6);
+        assert_eq!(stats.host_calls, 1);
+        assert_eq!(stats.memory_grown, 2 * 65536);
+        assert_eq!(stats.max_call_depth, 2);
+        assert_eq!(stats.instructions, stats.instruction_counts.values().sum::<u64>());
+        assert_eq!(stats.instruction_counts["Call"], 2);
+        assert_eq!(stats.instruction_counts["I32Add"], 3);
+        assert_eq!(stats.instruction_counts["BrIf"], 4);
+
+        // the loop body runs once per iteration, the exit check once more
+        let (_, more) = run.call_with_stats(&mut store, 10).unwrap();
+        assert_eq!(more.instruction_counts["I32Add"], 10);
+        assert_eq!(more.instruction_counts["BrIf"], 11);
+        assert_eq!(more.host_calls, 1);
+
+        // calls without stats don't record anything
+        assert_eq!(run.call(&mut store, 4).unwrap(), 10);
+        assert!(store.call_stats.is_none());
+    }
+}
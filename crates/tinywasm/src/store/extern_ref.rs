@@ -0,0 +1,248 @@
+use alloc::{boxed::Box, collections::BTreeMap, collections::BTreeSet, string::ToString, vec::Vec};
+use core::any::Any;
+use tinywasm_types::{ExternAddr, ValType, WasmValue};
+
+use super::host_data::AnyData;
+use super::{Store, TableElement};
+use crate::interpreter::stack::{CallFrame, CallStack, Stack};
+use crate::interpreter::{TinyWasmValue, ValueRef};
+use crate::{Error, MaybeSend, Result};
+
+/// A reference to a host object in a store, see [`Store::new_extern_ref`]
+///
+/// Converts to and from `WasmValue::RefExtern`, so it can be passed to and returned from functions,
+/// used in typed host functions and stored in tables and globals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExternRef(ExternAddr);
+
+impl ExternRef {
+    /// Get the address of the referenced object in the store
+    pub fn addr(&self) -> ExternAddr {
+        self.0
+    }
+}
+
+impl From<ExternRef> for WasmValue {
+    fn from(value: ExternRef) -> Self {
+        WasmValue::RefExtern(value.0)
+    }
+}
+
+impl TryFrom<WasmValue> for ExternRef {
+    type Error = ();
+
+    fn try_from(value: WasmValue) -> core::result::Result<Self, Self::Error> {
+        match value {
+            WasmValue::RefExtern(addr) => Ok(Self(addr)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Host objects referenced by `externref` values
+///
+/// Addresses are never reused, so references to removed objects can't point to newer objects.
+#[derive(Default)]
+pub(crate) struct ExternRefs {
+    objects: BTreeMap<ExternAddr, AnyData>,
+    next: ExternAddr,
+
+    // references of code that is calling a host function, moved here for the duration of the call,
+    // these also contain function references since the stacks don't distinguish between reference types
+    suspended: Vec<SuspendedRefs>,
+    // reused reference stacks for host calls, so calls don't allocate
+    buffers: Vec<Vec<ValueRef>>,
+}
+
+struct SuspendedRefs {
+    values: Vec<ValueRef>,
+    locals: Box<[ValueRef]>,
+    frames: CallStack,
+}
+
+impl ExternRefs {
+    // move the references of code calling a host function into the store, leaving only copies of the
+    // `params` references the host function reads from the top of the stack. Code can't reference host
+    // objects if there are none, so nothing is moved then and `false` is returned
+    #[inline]
+    pub(crate) fn suspend(&mut self, stack: &mut Stack, cf: &mut CallFrame, params: usize) -> bool {
+        if self.objects.is_empty() {
+            return false;
+        }
+
+        let mut active = self.buffers.pop().unwrap_or_default();
+        let values = &mut stack.values.stack_ref;
+        active.extend_from_slice(&values[values.len() - params..]);
+        let values = core::mem::replace(values, active);
+        let locals = core::mem::take(&mut cf.locals.locals_ref);
+        let frames = core::mem::take(&mut stack.call_stack);
+        self.suspended.push(SuspendedRefs { values, locals, frames });
+        true
+    }
+
+    // move the references back once the host call returned, replacing the params with the results
+    #[inline]
+    pub(crate) fn resume(&mut self, stack: &mut Stack, cf: &mut CallFrame, params: usize) {
+        let SuspendedRefs { mut values, locals, frames } =
+            self.suspended.pop().expect("no suspended call, this is a bug");
+        values.truncate(values.len() - params);
+        values.append(&mut stack.values.stack_ref);
+        self.buffers.push(core::mem::replace(&mut stack.values.stack_ref, values));
+        cf.locals.locals_ref = locals;
+        stack.call_stack = frames;
+    }
+
+    fn suspended_refs(&self) -> impl Iterator<Item = ExternAddr> + '_ {
+        self.suspended.iter().flat_map(|refs| {
+            let frames = refs.frames.frames().flat_map(|cf| cf.locals.locals_ref.iter());
+            refs.values.iter().chain(refs.locals.iter()).chain(frames).flatten().copied()
+        })
+    }
+
+    // whether the address was handed out by this store, the object might have been removed since
+    pub(crate) fn is_allocated(&self, addr: ExternAddr) -> bool {
+        addr < self.next
+    }
+}
+
+impl Store {
+    /// Add a host object to the store and get a reference to it, which can be passed to WebAssembly as an `externref`
+    ///
+    /// The object stays in the store until it is removed using [`Store::remove_extern_ref`]
+    /// or reclaimed by [`Store::collect_extern_refs`].
+    pub fn new_extern_ref<T: Any + MaybeSend>(&mut self, value: T) -> Result<ExternRef> {
+        let refs = &mut self.extern_refs;
+        let addr = refs.next;
+        refs.next = addr.checked_add(1).ok_or_else(|| Error::Other("too many extern references".to_string()))?;
+        refs.objects.insert(addr, Box::new(value));
+        Ok(ExternRef(addr))
+    }
+
+    /// Get a reference to a host object, if it still exists and is of type `T`
+    pub fn extern_data<T: Any>(&self, extern_ref: ExternRef) -> Option<&T> {
+        self.extern_refs.objects.get(&extern_ref.0)?.downcast_ref()
+    }
+
+    /// Get a mutable reference to a host object, if it still exists and is of type `T`
+    pub fn extern_data_mut<T: Any>(&mut self, extern_ref: ExternRef) -> Option<&mut T> {
+        self.extern_refs.objects.get_mut(&extern_ref.0)?.downcast_mut()
+    }
+
+    /// Remove a host object from the store, returning whether it existed
+    ///
+    /// References to it that are still used by WebAssembly code stay valid, but can't be resolved anymore.
+    pub fn remove_extern_ref(&mut self, extern_ref: ExternRef) -> bool {
+        self.extern_refs.objects.remove(&extern_ref.0).is_some()
+    }
+
+    /// Remove all host objects that aren't referenced by WebAssembly, returning the number of removed objects
+    ///
+    /// Objects are kept if they are referenced by a table or global, or by the value stack or locals of
+    /// code that is calling the host function doing the collection. References only held by the host
+    /// are not taken into account, so those objects are removed as well.
+    pub fn collect_extern_refs(&mut self) -> usize {
+        let data = &self.data;
+        let mut reachable = BTreeSet::new();
+        for table in data.tables.iter().filter(|table| table.kind.element_type == ValType::RefExtern) {
+            reachable.extend(table.elements.iter().filter_map(TableElement::addr));
+        }
+
+        for global in data.globals.iter().filter(|global| global.ty.ty == ValType::RefExtern) {
+            if let TinyWasmValue::ValueRef(Some(addr)) = global.value.get() {
+                reachable.insert(addr);
+            }
+        }
+
+        reachable.extend(self.instance_states.extern_refs(data));
+
+        let refs = &mut self.extern_refs;
+        reachable.extend(refs.suspended_refs());
+
+        let count = refs.objects.len();
+        refs.objects.retain(|addr, _| reachable.contains(addr));
+        count - refs.objects.len()
+    }
+}
+
+#[cfg(test)]
+mod extern_ref_tests {
+    use super::*;
+
+    #[test]
+    fn test_extern_refs() {
+        let mut store = Store::default();
+        let a = store.new_extern_ref(1u32).unwrap();
+        let b = store.new_extern_ref("b").unwrap();
+        assert_ne!(a, b);
+
+        assert_eq!(store.extern_data::<u32>(a), Some(&1));
+        assert_eq!(store.extern_data::<u64>(a), None);
+        *store.extern_data_mut::<u32>(a).unwrap() += 1;
+        assert_eq!(store.extern_data::<u32>(a), Some(&2));
+        assert_eq!(store.extern_data::<&str>(b), Some(&"b"));
+
+        // addresses aren't reused after removal
+        assert!(store.remove_extern_ref(a));
+        assert!(!store.remove_extern_ref(a));
+        assert_eq!(store.extern_data::<u32>(a), None);
+        let c = store.new_extern_ref(3u32).unwrap();
+        assert!(c.addr() > b.addr());
+
+        // nothing references b and c
+        assert_eq!(store.collect_extern_refs(), 2);
+        assert_eq!(store.extern_data::<&str>(b), None);
+        assert_eq!(store.collect_extern_refs(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_extern_refs_in_wasm() {
+        use crate::{Extern, FuncContext, Imports};
+
+        let module = crate::test_util::module(
+            r#"(module
+                (import "env" "collect" (func $collect))
+                (table 1 externref)
+                (func (export "store") (param externref) (table.set (i32.const 0) (local.get 0)))
+                (func (export "load") (result externref) (table.get (i32.const 0)))
+                (func (export "local") (param externref) (result externref) (call $collect) (local.get 0))
+                (func (export "stack") (param externref) (result externref) (local.get 0) (call $collect))
+                (func $inner (call $collect))
+                (func (export "frame") (param externref) (result externref) (call $inner) (local.get 0)))"#,
+        );
+
+        let mut imports = Imports::new();
+        let collect = Extern::typed_func(|mut ctx: FuncContext<'_>, _: ()| {
+            ctx.store_mut().collect_extern_refs();
+            Ok(())
+        });
+        imports.define("env", "collect", collect).unwrap();
+
+        let mut store = Store::default();
+        let instance = module.instantiate(&mut store, Some(imports)).unwrap();
+
+        // references held by code calling the host function are kept
+        for name in ["local", "stack", "frame"] {
+            let object = store.new_extern_ref(name).unwrap();
+            let func = instance.exported_func::<ExternRef, ExternRef>(&store, name).unwrap();
+            assert_eq!(func.call(&mut store, object).unwrap(), object);
+            assert_eq!(store.extern_data::<&str>(object), Some(&name));
+            assert_eq!(store.collect_extern_refs(), 1);
+        }
+
+        // round-trip through a table
+        let object = store.new_extern_ref(42u32).unwrap();
+        let set = instance.exported_func::<Option<ExternRef>, ()>(&store, "store").unwrap();
+        let get = instance.exported_func::<(), Option<ExternRef>>(&store, "load").unwrap();
+        set.call(&mut store, Some(object)).unwrap();
+        assert_eq!(store.collect_extern_refs(), 0);
+        let loaded = get.call(&mut store, ()).unwrap().unwrap();
+        assert_eq!(loaded, object);
+        assert_eq!(store.extern_data::<u32>(loaded), Some(&42));
+
+        set.call(&mut store, None).unwrap();
+        assert_eq!(get.call(&mut store, ()).unwrap(), None);
+        assert_eq!(store.collect_extern_refs(), 1);
+        assert_eq!(store.extern_data::<u32>(object), None);
+    }
+}
@@ -0,0 +1,270 @@
+use alloc::{vec, vec::Vec};
+use tinywasm_types::{ModuleInstanceAddr, ValType, WasmFunction};
+
+use super::{Store, TableElement};
+use crate::interpreter::TinyWasmValue;
+use crate::{Error, Function, Rc, Result};
+
+impl Store {
+    /// Remove a module instance from the store
+    ///
+    /// The instance can't be used anymore afterwards, and its exports can't be imported by new instances.
+    /// Its functions, memories, tables, globals and segments are reclaimed immediately if no other instance
+    /// references them, either through imports or through function references in tables and globals.
+    /// Otherwise, they are reclaimed by [`Store::collect_garbage`] once all referencing instances are removed as well.
+    ///
+    /// Removing an instance from a host function it called makes the call fail with [`Error::InstanceRemoved`]
+    /// once the host function returns. Calls and returns into instances that were already reclaimed fail the same way.
+    ///
+    /// Addresses are never reused, so reclaiming only frees the contents of functions, memories, tables and segments,
+    /// and the store still grows by a few bytes for each of them with every instantiation.
+    pub fn remove_instance(&mut self, addr: ModuleInstanceAddr) -> Result<()> {
+        if self.get_module_instance(addr).is_none() {
+            return Err(Error::InstanceRemoved);
+        }
+
+        self.removed_instances.insert(addr);
+        self.collect_garbage();
+        Ok(())
+    }
+
+    /// Reclaim all removed instances that aren't referenced by any other instance anymore
+    ///
+    /// Returns the number of reclaimed instances.
+    pub fn collect_garbage(&mut self) -> usize {
+        let reachable = self.reachable_instances();
+        let unreachable: Vec<_> = self.removed_instances.iter().copied().filter(|i| !reachable[*i as usize]).collect();
+        for addr in &unreachable {
+            self.reclaim_instance(*addr);
+        }
+        unreachable.len()
+    }
+
+    pub(crate) fn is_removed(&self, addr: ModuleInstanceAddr) -> bool {
+        self.removed_instances.contains(&addr) || self.module_instances.get(addr as usize).is_some_and(Option::is_none)
+    }
+
+    // mark all instances reachable from the instances that weren't removed
+    fn reachable_instances(&self) -> Vec<bool> {
+        let data = &self.data;
+        let mut reachable = vec![false; self.module_instances.len()];
+        let mut queue: Vec<ModuleInstanceAddr> =
+            (0..self.module_instances.len() as ModuleInstanceAddr).filter(|addr| !self.is_removed(*addr)).collect();
+
+        let func_owner =
+            |elem: &TableElement| elem.addr().and_then(|addr| data.funcs.get(addr as usize)).map(|f| f.owner);
+        while let Some(addr) = queue.pop() {
+            if core::mem::replace(&mut reachable[addr as usize], true) {
+                continue;
+            }
+
+            let Some(instance) = &self.module_instances[addr as usize] else { continue };
+            let instance = &instance.0;
+            let mut owners = Vec::new();
+
+            // imported items and references to functions
+            owners.extend(instance.func_addrs.iter().map(|a| data.funcs[*a as usize].owner));
+            owners.extend(instance.mem_addrs.iter().map(|a| data.memories[*a as usize]._owner));
+            for a in instance.table_addrs.iter() {
+                let table = &data.tables[*a as usize];
+                owners.push(table._owner);
+                owners.extend(table.elements.iter().filter_map(func_owner));
+            }
+            for a in instance.global_addrs.iter() {
+                let global = &data.globals[*a as usize];
+                owners.push(global._owner);
+                if let (ValType::RefFunc, TinyWasmValue::ValueRef(Some(func))) = (global.ty.ty, global.value.get()) {
+                    owners.extend(data.funcs.get(func as usize).map(|f| f.owner));
+                }
+            }
+            for a in instance.elem_addrs.iter() {
+                owners.extend(data.elements[*a as usize].items.iter().flatten().filter_map(func_owner));
+            }
+            owners.extend(self.instance_states.func_refs(addr).filter_map(func_owner));
+
+            queue.extend(owners.into_iter().filter(|owner| reachable.get(*owner as usize) == Some(&false)));
+        }
+
+        reachable
+    }
+
+    // free everything owned by an instance, the slots in the store are kept so addresses stay valid
+    fn reclaim_instance(&mut self, addr: ModuleInstanceAddr) {
+        let Some(instance) = self.module_instances[addr as usize].take() else { return };
+        self.removed_instances.remove(&addr);
+        self.instance_states.remove(addr);
+
+        let data = &mut self.data;
+        let placeholder = Rc::new(WasmFunction::default());
+        for a in instance.0.func_addrs.iter() {
+            let func = &mut data.funcs[*a as usize];
+            if func.owner == addr {
+                func.func = Function::Wasm(placeholder.clone());
+            }
+        }
+
+        for a in instance.0.mem_addrs.iter() {
+            let mem = &mut data.memories[*a as usize];
+            if mem._owner == addr {
+                mem.data = Vec::new();
+                mem.page_count = 0;
+                mem.dirty = None;
+            }
+        }
+
+        for a in instance.0.table_addrs.iter() {
+            let table = &mut data.tables[*a as usize];
+            if table._owner == addr {
+                table.elements = Vec::new();
+            }
+        }
+
+        for a in instance.0.global_addrs.iter() {
+            let global = &mut data.globals[*a as usize];
+            if global._owner == addr && matches!(global.ty.ty, ValType::RefFunc | ValType::RefExtern) {
+                global.value.set(TinyWasmValue::ValueRef(None));
+            }
+        }
+
+        instance.0.elem_addrs.iter().for_each(|a| data.elements[*a as usize].items = None);
+        instance.0.data_addrs.iter().for_each(|a| data.datas[*a as usize].data = None);
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod gc_tests {
+    use crate::test_util::module;
+    use crate::{Error, Extern, FuncContext, Imports, ModuleInstance, Store};
+
+    fn instantiate(store: &mut Store, wat: &str, imports: Imports) -> ModuleInstance {
+        module(wat).instantiate(store, Some(imports)).unwrap()
+    }
+
+    #[test]
+    fn test_remove_in_host_call() {
+        let wat = r#"(module
+            (import "env" "remove" (func $remove))
+            (func $g (result i32) (i32.const 1))
+            (func (export "call") (result i32) (call $remove) (call $g))
+            (func (export "return") (result i32) (call $remove) (i32.const 1)))"#;
+
+        for name in ["call", "return"] {
+            let mut store = Store::default();
+            let mut imports = Imports::new();
+            let remove = Extern::typed_func(|mut ctx: FuncContext<'_>, _: ()| {
+                let id = ctx.module().id();
+                ctx.store_mut().remove_instance(id)
+            });
+            imports.define("env", "remove", remove).unwrap();
+
+            let instance = instantiate(&mut store, wat, imports);
+            let func = instance.exported_func::<(), i32>(&store, name).unwrap();
+            assert!(matches!(func.call(&mut store, ()), Err(Error::InstanceRemoved)));
+            assert!(store.module_instances[instance.id() as usize].is_none());
+            assert!(matches!(func.call(&mut store, ()), Err(Error::InstanceRemoved)));
+        }
+    }
+
+    #[test]
+    fn test_return_into_reclaimed_instance() {
+        let mut store = Store::default();
+        let mut imports = Imports::new();
+        let remove =
+            Extern::typed_func(|mut ctx: FuncContext<'_>, addr: i32| ctx.store_mut().remove_instance(addr as u32));
+        imports.define("env", "remove", remove).unwrap();
+        let b = instantiate(
+            &mut store,
+            r#"(module
+                (import "env" "remove" (func $remove (param i32)))
+                (func (export "remove") (param i32) (call $remove (local.get 0))))"#,
+            imports,
+        );
+
+        let mut imports = Imports::new();
+        imports.link_module("b", b.id()).unwrap();
+        let a = instantiate(
+            &mut store,
+            r#"(module
+                (import "b" "remove" (func $remove (param i32)))
+                (func (export "run") (param i32) (result i32) (call $remove (local.get 0)) (i32.const 1)))"#,
+            imports,
+        );
+
+        let run = a.exported_func::<i32, i32>(&store, "run").unwrap();
+        assert!(matches!(run.call(&mut store, a.id() as i32), Err(Error::InstanceRemoved)));
+        assert!(store.get_module_instance(b.id()).is_some());
+        let frames = store.last_backtrace().unwrap().frames();
+        assert_eq!(frames[0].module_addr, a.id());
+        assert_eq!(frames[0].func_index, None);
+    }
+
+    #[test]
+    fn test_reachable_through_imports() {
+        let mut store = Store::default();
+        let a = instantiate(
+            &mut store,
+            r#"(module (memory (export "memory") 1) (func (export "f") (result i32) (i32.const 42)))"#,
+            Imports::new(),
+        );
+
+        let mut imports = Imports::new();
+        imports.link_module("a", a.id()).unwrap();
+        let b = instantiate(
+            &mut store,
+            r#"(module
+                (import "a" "f" (func $f (result i32)))
+                (import "a" "memory" (memory 1))
+                (func (export "g") (result i32) (call $f)))"#,
+            imports,
+        );
+
+        // a is still imported by b, so it can't be used directly anymore but isn't reclaimed
+        store.remove_instance(a.id()).unwrap();
+        assert!(store.get_module_instance(a.id()).is_none());
+        assert!(matches!(store.remove_instance(a.id()), Err(Error::InstanceRemoved)));
+        assert!(store.module_instances[a.id() as usize].is_some());
+        assert_eq!(b.exported_func::<(), i32>(&store, "g").unwrap().call(&mut store, ()).unwrap(), 42);
+
+        store.remove_instance(b.id()).unwrap();
+        assert!(store.module_instances.iter().all(Option::is_none));
+        assert!(store.data.memories[0].data.is_empty());
+        assert_eq!(store.collect_garbage(), 0);
+    }
+
+    #[test]
+    fn test_reachable_through_tables() {
+        let mut store = Store::default();
+        let b = instantiate(
+            &mut store,
+            r#"(module
+                (table (export "table") 1 funcref)
+                (func (export "call") (result i32) (call_indirect (result i32) (i32.const 0)))
+                (func (export "clear") (table.set (i32.const 0) (ref.null func))))"#,
+            Imports::new(),
+        );
+
+        // a only stores a function reference in b's table
+        let mut imports = Imports::new();
+        imports.link_module("b", b.id()).unwrap();
+        let a = instantiate(
+            &mut store,
+            r#"(module
+                (import "b" "table" (table 1 funcref))
+                (func $f (result i32) (i32.const 7))
+                (elem (i32.const 0) $f))"#,
+            imports,
+        );
+
+        store.remove_instance(a.id()).unwrap();
+        assert!(store.module_instances[a.id() as usize].is_some());
+        let call = b.exported_func::<(), i32>(&store, "call").unwrap();
+        assert_eq!(call.call(&mut store, ()).unwrap(), 7);
+
+        // once the reference is gone, a can be collected
+        assert_eq!(store.collect_garbage(), 0);
+        b.exported_func::<(), ()>(&store, "clear").unwrap().call(&mut store, ()).unwrap();
+        assert_eq!(store.collect_garbage(), 1);
+        assert!(store.module_instances[a.id() as usize].is_none());
+        assert_eq!(store.collect_garbage(), 0);
+    }
+}
@@ -0,0 +1,94 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::any::{Any, TypeId};
+
+use super::Store;
+use crate::MaybeSend;
+
+#[cfg(not(feature = "sync"))]
+pub(crate) type AnyData = Box<dyn Any>;
+#[cfg(feature = "sync")]
+pub(crate) type AnyData = Box<dyn Any + Send>;
+
+/// User data attached to a store, with at most one value per type
+#[derive(Default)]
+pub(crate) struct HostData(BTreeMap<TypeId, AnyData>);
+
+impl Store {
+    /// Attach a value to the store, returning the previous value of the same type
+    ///
+    /// Each type can only be attached once, so use a newtype or a struct to store multiple values of the same type.
+    /// The value can be accessed from host functions using [`crate::FuncContext::data`].
+    pub fn insert_data<T: Any + MaybeSend>(&mut self, data: T) -> Option<T> {
+        let previous = self.host_data.0.insert(TypeId::of::<T>(), Box::new(data))?;
+        previous.downcast().ok().map(|data| *data)
+    }
+
+    /// Get a reference to the value of type `T` attached to the store
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.host_data.0.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Get a mutable reference to the value of type `T` attached to the store
+    pub fn data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.host_data.0.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Remove the value of type `T` from the store and return it
+    pub fn remove_data<T: Any>(&mut self) -> Option<T> {
+        let data = self.host_data.0.remove(&TypeId::of::<T>())?;
+        data.downcast().ok().map(|data| *data)
+    }
+}
+
+#[cfg(test)]
+mod host_data_tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Counter(u32);
+
+    #[test]
+    fn test_host_data() {
+        let mut store = Store::default();
+        assert_eq!(store.data::<Counter>(), None);
+
+        assert_eq!(store.insert_data(Counter(1)), None);
+        assert_eq!(store.insert_data(5u64), None);
+        store.data_mut::<Counter>().unwrap().0 += 1;
+        assert_eq!(store.data::<Counter>(), Some(&Counter(2)));
+
+        // values are stored per type
+        assert_eq!(store.insert_data(Counter(10)), Some(Counter(2)));
+        assert_eq!(store.data::<u64>(), Some(&5));
+        assert_eq!(store.data::<u32>(), None);
+
+        assert_eq!(store.remove_data::<Counter>(), Some(Counter(10)));
+        assert_eq!(store.remove_data::<Counter>(), None);
+        assert_eq!(store.data::<u64>(), Some(&5));
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_host_data_in_host_function() {
+        use crate::{Extern, FuncContext, Imports};
+
+        let module = crate::test_util::module(
+            r#"(module
+                (import "env" "add" (func $add (param i32)))
+                (func (export "run") (call $add (i32.const 2)) (call $add (i32.const 3))))"#,
+        );
+
+        let mut imports = Imports::new();
+        let add = Extern::typed_func(|mut ctx: FuncContext<'_>, n: i32| {
+            ctx.data_mut::<Counter>().unwrap().0 += n as u32;
+            Ok(())
+        });
+        imports.define("env", "add", add).unwrap();
+
+        let mut store = Store::default();
+        store.insert_data(Counter(1));
+        let instance = module.instantiate(&mut store, Some(imports)).unwrap();
+        instance.exported_func::<(), ()>(&store, "run").unwrap().call(&mut store, ()).unwrap();
+        assert_eq!(store.data::<Counter>(), Some(&Counter(6)));
+    }
+}
@@ -1,5 +1,6 @@
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use tinywasm_types::{MemoryType, ModuleInstanceAddr};
 
 use crate::{cold, log, Error, Result};
@@ -12,9 +13,57 @@ pub(crate) struct MemoryInstance {
     pub(crate) kind: MemoryType,
     pub(crate) data: Vec<u8>,
     pub(crate) page_count: usize,
+    pub(crate) dirty: Option<DirtyPages>,
     pub(crate) _owner: ModuleInstanceAddr, // index into store.module_instances
 }
 
+// global counter for the epochs of dirty page trackers
+static DIRTY_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Tracks which pages of a memory were written to
+///
+/// Pages are `page_size` bytes large, independent of the page size of the memory itself.
+#[derive(Debug, Clone)]
+pub(crate) struct DirtyPages {
+    pub(crate) page_size: usize,
+    pub(crate) epoch: usize, // unique id of the current set of dirty pages, changes every time they are cleared
+    bits: Vec<u64>,
+}
+
+impl DirtyPages {
+    pub(crate) fn new(page_size: usize) -> Self {
+        Self { page_size: page_size.max(1), epoch: DIRTY_EPOCH.fetch_add(1, Ordering::Relaxed), bits: Vec::new() }
+    }
+
+    #[inline]
+    fn mark(&mut self, addr: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let (first, last) = (addr / self.page_size, (addr + len - 1) / self.page_size);
+        if last / 64 >= self.bits.len() {
+            self.bits.resize(last / 64 + 1, 0);
+        }
+
+        for page in first..=last {
+            self.bits[page / 64] |= 1 << (page % 64);
+        }
+    }
+
+    pub(crate) fn pages(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bits
+            .iter()
+            .enumerate()
+            .flat_map(|(i, word)| (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| i * 64 + bit))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bits.clear();
+        self.epoch = DIRTY_EPOCH.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 impl MemoryInstance {
     pub(crate) fn new(kind: MemoryType, owner: ModuleInstanceAddr) -> Self {
         assert!(kind.page_count_initial() <= kind.page_count_max());
@@ -24,6 +73,7 @@ impl MemoryInstance {
             kind,
             data: vec![0; kind.initial_size() as usize],
             page_count: kind.page_count_initial() as usize,
+            dirty: None,
             _owner: owner,
         }
     }
@@ -33,6 +83,13 @@ impl MemoryInstance {
         self.data.len()
     }
 
+    #[inline(always)]
+    pub(crate) fn mark_dirty(&mut self, addr: usize, len: usize) {
+        if let Some(dirty) = &mut self.dirty {
+            dirty.mark(addr, len);
+        }
+    }
+
     #[inline(never)]
     #[cold]
     fn trap_oob(&self, addr: usize, len: usize) -> Error {
@@ -50,6 +107,7 @@ impl MemoryInstance {
             return Err(self.trap_oob(addr, data.len()));
         }
         self.data[addr..end].copy_from_slice(data);
+        self.mark_dirty(addr, len);
         Ok(())
     }
 
@@ -92,6 +150,7 @@ impl MemoryInstance {
             return Err(self.trap_oob(addr, len));
         }
         self.data[addr..end].fill_with(|| val);
+        self.mark_dirty(addr, len);
         Ok(())
     }
 
@@ -102,6 +161,7 @@ impl MemoryInstance {
         }
 
         self.data[dst..end].copy_from_slice(src);
+        self.mark_dirty(dst, src.len());
         Ok(())
     }
 
@@ -120,9 +180,35 @@ impl MemoryInstance {
 
         // Perform the copy
         self.data.copy_within(src..src_end, dst);
+        self.mark_dirty(dst, len);
         Ok(())
     }
 
+    // shrink the memory to `page_count` pages and replace its contents with the given sorted non-zero ranges
+    // if `dirty_only` is set, only the pages marked as dirty are restored
+    pub(crate) fn reset(&mut self, page_count: usize, segments: &[(usize, Vec<u8>)], dirty_only: bool) {
+        self.data.truncate(page_count * self.kind.page_size() as usize);
+        self.page_count = page_count;
+
+        match (dirty_only, &self.dirty) {
+            (true, Some(dirty)) => {
+                let len = self.data.len();
+                let ranges = dirty.pages().map(|page| page * dirty.page_size..((page + 1) * dirty.page_size).min(len));
+                for range in ranges.take_while(|range| range.start < len) {
+                    restore_range(&mut self.data, range, segments);
+                }
+            }
+            _ => {
+                let len = self.data.len();
+                restore_range(&mut self.data, 0..len, segments);
+            }
+        }
+
+        if let Some(dirty) = &mut self.dirty {
+            dirty.clear();
+        }
+    }
+
     #[inline]
     pub(crate) fn grow(&mut self, pages_delta: i32) -> Option<i32> {
         let current_pages = self.page_count;
@@ -146,6 +232,16 @@ impl MemoryInstance {
     }
 }
 
+// zero a range of memory and copy the overlapping parts of the sorted segments into it
+fn restore_range(data: &mut [u8], range: core::ops::Range<usize>, segments: &[(usize, Vec<u8>)]) {
+    data[range.clone()].fill(0);
+    let first = segments.partition_point(|(offset, bytes)| offset + bytes.len() <= range.start);
+    for (offset, bytes) in segments[first..].iter().take_while(|(offset, _)| *offset < range.end) {
+        let (start, end) = ((*offset).max(range.start), (offset + bytes.len()).min(range.end));
+        data[start..end].copy_from_slice(&bytes[start - offset..end - offset]);
+    }
+}
+
 /// A trait for types that can be stored in memory
 pub(crate) trait MemStorable<const N: usize> {
     /// Store a value in memory
@@ -279,4 +375,24 @@ mod memory_instance_tests {
         let loaded_data = memory.load(0, data_to_store.len()).unwrap();
         assert_eq!(loaded_data, &data_to_store);
     }
+
+    #[test]
+    fn test_memory_dirty_pages() {
+        let mut memory = create_test_memory();
+        memory.dirty = Some(DirtyPages::new(1024));
+        memory.store(10, 2, &[1, 2]).unwrap();
+        memory.fill(2000, 100, 3).unwrap();
+        memory.copy_within(5000, 10, 2).unwrap();
+        assert_eq!(memory.dirty.as_ref().unwrap().pages().collect::<Vec<_>>(), vec![0, 1, 2, 4]);
+
+        // only dirty pages are restored, the rest of the memory is assumed to be unchanged
+        memory.data[3500] = 9;
+        memory.reset(1, &[(12, vec![7]), (2040, vec![8; 16])], true);
+        assert_eq!(&memory.data[10..13], &[0, 0, 7]);
+        assert_eq!(&memory.data[2040..2056], &[8; 16]);
+        assert_eq!(memory.data[2100], 0);
+        assert_eq!(memory.data[3500], 9);
+        assert_eq!(memory.data[5000], 0);
+        assert_eq!(memory.dirty.as_ref().unwrap().pages().count(), 0);
+    }
 }
@@ -1,38 +1,67 @@
+use alloc::collections::BTreeSet;
 use alloc::{boxed::Box, format, string::ToString, vec::Vec};
 use core::fmt::Debug;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use tinywasm_types::*;
 
+use crate::coverage::CoverageRecorder;
 use crate::interpreter::{self, InterpreterRuntime, TinyWasmValue};
-use crate::{cold, Error, Function, ModuleInstance, Result, Trap};
+use crate::profiler::Profiler;
+use crate::stats::StatsRecorder;
+use crate::{cold, Backtrace, Coverage, Error, Function, ModuleInstance, Profile, Rc, Result, Trap};
 
 mod data;
 mod element;
+mod extern_ref;
 mod function;
+mod gc;
 mod global;
+mod host_data;
 mod memory;
+mod reset;
+mod snapshot;
 mod table;
 
+pub use extern_ref::ExternRef;
+pub(crate) use extern_ref::ExternRefs;
+pub(crate) use host_data::HostData;
+pub(crate) use reset::InstanceStates;
+pub use snapshot::Snapshot;
 pub(crate) use {data::*, element::*, function::*, global::*, memory::*, table::*};
 
 // global store id counter
 static STORE_ID: AtomicUsize = AtomicUsize::new(0);
 
+// owner of memories, tables and globals created by the host, these are never reclaimed
+pub(crate) const HOST_OWNER: ModuleInstanceAddr = ModuleInstanceAddr::MAX;
+
 /// Global state that can be manipulated by WebAssembly programs
 ///
 /// Data should only be addressable by the module that owns it
 ///
-/// Note that the state doesn't do any garbage collection - so it will grow
-/// indefinitely if you keep adding modules to it. When calling temporary
-/// functions, you should create a new store and then drop it when you're done (e.g. in a request handler)
+/// Instances stay in the store until they are removed using [`Store::remove_instance`].
+/// Removed instances are reclaimed once no other instance references them anymore,
+/// but a small amount of bookkeeping data is kept for every instance ever added.
+/// When calling temporary functions, you should create a new store and then drop it when you're done
+/// (e.g. in a request handler), or reuse an instance using [`ModuleInstance::reset`].
 ///
 ///  See <https://webassembly.github.io/spec/core/exec/runtime.html#store>
 pub struct Store {
     id: usize,
-    module_instances: Vec<ModuleInstance>,
+    module_instances: Vec<Option<ModuleInstance>>, // none if the instance was reclaimed
+    removed_instances: BTreeSet<ModuleInstanceAddr>,
 
     pub(crate) data: StoreData,
+    pub(crate) instance_states: InstanceStates,
+    pub(crate) instance_reset: bool,
+    pub(crate) host_data: HostData,
+    pub(crate) extern_refs: ExternRefs,
     pub(crate) runtime: Runtime,
+    pub(crate) last_backtrace: Option<Backtrace>,
+    pub(crate) call_depth: u32, // number of calls into the store that haven't returned yet
+    pub(crate) profiler: Option<Profiler>,
+    pub(crate) coverage: Option<CoverageRecorder>,
+    pub(crate) call_stats: Option<StatsRecorder>,
 }
 
 impl Debug for Store {
@@ -40,8 +69,14 @@ impl Debug for Store {
         f.debug_struct("Store")
             .field("id", &self.id)
             .field("module_instances", &self.module_instances)
+            .field("removed_instances", &self.removed_instances)
+            .field("instance_reset", &self.instance_reset)
             .field("data", &"...")
             .field("runtime", &self.runtime)
+            .field("last_backtrace", &self.last_backtrace)
+            .field("profiler", &self.profiler.is_some())
+            .field("coverage", &self.coverage.is_some())
+            .field("call_stats", &self.call_stats.is_some())
             .finish()
     }
 }
@@ -58,14 +93,72 @@ impl Store {
     }
 
     /// Get a module instance by the internal id
+    ///
+    /// Returns `None` if the instance was removed using [`Store::remove_instance`]
     pub fn get_module_instance(&self, addr: ModuleInstanceAddr) -> Option<&ModuleInstance> {
-        self.module_instances.get(addr as usize)
+        if self.removed_instances.contains(&addr) {
+            return None;
+        }
+        self.module_instances.get(addr as usize)?.as_ref()
     }
 
-    pub(crate) fn get_module_instance_raw(&self, addr: ModuleInstanceAddr) -> ModuleInstance {
+    // get an instance even if it was removed, returns none if it was already reclaimed
+    pub(crate) fn get_module_instance_raw(&self, addr: ModuleInstanceAddr) -> Option<ModuleInstance> {
         self.module_instances[addr as usize].clone()
     }
 
+    /// Start sampling the call stack every `interval` executed instructions
+    ///
+    /// Restarts the profiler if it is already running. Only code executed by the interpreter is sampled,
+    /// calls made from host functions show up as separate call stacks.
+    pub fn start_profiling(&mut self, interval: u64) {
+        self.profiler = Some(Profiler::new(interval));
+    }
+
+    /// Stop the profiler and return the recorded profile, if it was running
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        let profiler = self.profiler.take()?;
+        Some(profiler.finish(|addr| {
+            let (owner, idx) = self.func_index(addr);
+            let Some(idx) = idx else { return format!("module[{owner}]::<unknown>") };
+            let name = self.module_instances[owner as usize].as_ref().and_then(|owner| owner.func_name(idx));
+            name.map_or_else(|| format!("func[{idx}]"), ToString::to_string)
+        }))
+    }
+
+    /// Start recording which instructions and branches are executed
+    ///
+    /// Discards previously recorded coverage if coverage is already being recorded.
+    pub fn start_coverage(&mut self) {
+        self.coverage = Some(CoverageRecorder::default());
+    }
+
+    /// Stop recording coverage and return the recorded coverage, if it was running
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        let coverage = self.coverage.take()?;
+        Some(coverage.finish(|addr| self.func_index(addr)))
+    }
+
+    // get the owning module instance and the function index in that module of a function,
+    // the index is unknown if the owning instance was reclaimed
+    fn func_index(&self, addr: FuncAddr) -> (ModuleInstanceAddr, Option<u32>) {
+        let owner = self.data.funcs[addr as usize].owner;
+        let instance = self.module_instances[owner as usize].as_ref();
+        let idx = instance.and_then(|instance| instance.func_addrs().iter().position(|a| *a == addr));
+        (owner, idx.map(|idx| idx as u32))
+    }
+
+    /// Get the WebAssembly call stack of the most recent error raised while executing code in this store
+    ///
+    /// Errors returned from calls don't carry the location of the trap, so embedders that want to
+    /// report where an error happened need to query this after the call returned.
+    /// The backtrace is cleared when a new call into the store starts, except for calls made by host functions.
+    /// Source locations are only available if the module was parsed with the `dwarf` feature
+    /// and contains DWARF line information.
+    pub fn last_backtrace(&self) -> Option<&Backtrace> {
+        self.last_backtrace.as_ref()
+    }
+
     /// Create a new store with the given runtime
     pub(crate) fn runtime(&self) -> interpreter::InterpreterRuntime {
         match self.runtime {
@@ -83,7 +176,22 @@ impl PartialEq for Store {
 impl Default for Store {
     fn default() -> Self {
         let id = STORE_ID.fetch_add(1, Ordering::Relaxed);
-        Self { id, module_instances: Vec::new(), data: StoreData::default(), runtime: Runtime::Default }
+        Self {
+            id,
+            module_instances: Vec::new(),
+            removed_instances: BTreeSet::new(),
+            data: StoreData::default(),
+            instance_states: InstanceStates::default(),
+            instance_reset: false,
+            host_data: HostData::default(),
+            extern_refs: ExternRefs::default(),
+            runtime: Runtime::Default,
+            last_backtrace: None,
+            call_depth: 0,
+            profiler: None,
+            coverage: None,
+            call_stats: None,
+        }
     }
 }
 
@@ -107,13 +215,28 @@ impl Store {
         self.id
     }
 
+    /// Get a handle to the function referenced by a `RefFunc` value, e.g. one returned by a function or stored in a table
+    ///
+    /// Returns `None` for null references, other values and functions that don't exist in this store.
+    pub fn func_from_ref(&self, value: WasmValue) -> Option<crate::FuncHandle> {
+        let WasmValue::RefFunc(addr) = value else { return None };
+        let func = self.data.funcs.get(addr as usize)?;
+        Some(crate::FuncHandle {
+            store_id: self.id,
+            module_addr: func.owner,
+            addr,
+            ty: func.func.ty().clone(),
+            name: None,
+        })
+    }
+
     pub(crate) fn next_module_instance_idx(&self) -> ModuleInstanceAddr {
         self.module_instances.len() as ModuleInstanceAddr
     }
 
     pub(crate) fn add_instance(&mut self, instance: ModuleInstance) {
         assert!(instance.id() == self.module_instances.len() as ModuleInstanceAddr);
-        self.module_instances.push(instance);
+        self.module_instances.push(Some(instance));
     }
 
     #[cold]
@@ -217,9 +340,13 @@ impl Store {
 // Linking related functions
 impl Store {
     /// Add functions to the store, returning their addresses in the store
-    pub(crate) fn init_funcs(&mut self, funcs: Vec<WasmFunction>, idx: ModuleInstanceAddr) -> Result<Vec<FuncAddr>> {
+    pub(crate) fn init_funcs(
+        &mut self,
+        funcs: impl IntoIterator<Item = Rc<WasmFunction>>,
+        idx: ModuleInstanceAddr,
+    ) -> Result<Vec<FuncAddr>> {
         let func_count = self.data.funcs.len();
-        let mut func_addrs = Vec::with_capacity(func_count);
+        let mut func_addrs = Vec::new();
         for (i, func) in funcs.into_iter().enumerate() {
             self.data.funcs.push(FunctionInstance::new_wasm(func, idx));
             func_addrs.push((i + func_count) as FuncAddr);
@@ -228,21 +355,21 @@ impl Store {
     }
 
     /// Add tables to the store, returning their addresses in the store
-    pub(crate) fn init_tables(&mut self, tables: Vec<TableType>, idx: ModuleInstanceAddr) -> Result<Vec<TableAddr>> {
+    pub(crate) fn init_tables(&mut self, tables: &[TableType], idx: ModuleInstanceAddr) -> Result<Vec<TableAddr>> {
         let table_count = self.data.tables.len();
         let mut table_addrs = Vec::with_capacity(table_count);
-        for (i, table) in tables.into_iter().enumerate() {
-            self.data.tables.push(TableInstance::new(table, idx));
+        for (i, table) in tables.iter().enumerate() {
+            self.data.tables.push(TableInstance::new(table.clone(), idx));
             table_addrs.push((i + table_count) as TableAddr);
         }
         Ok(table_addrs)
     }
 
     /// Add memories to the store, returning their addresses in the store
-    pub(crate) fn init_memories(&mut self, memories: Vec<MemoryType>, idx: ModuleInstanceAddr) -> Result<Vec<MemAddr>> {
+    pub(crate) fn init_memories(&mut self, memories: &[MemoryType], idx: ModuleInstanceAddr) -> Result<Vec<MemAddr>> {
         let mem_count = self.data.memories.len();
         let mut mem_addrs = Vec::with_capacity(mem_count);
-        for (i, mem) in memories.into_iter().enumerate() {
+        for (i, mem) in memories.iter().copied().enumerate() {
             if let MemoryArch::I64 = mem.arch() {
                 return Err(Error::UnsupportedFeature("64-bit memories".to_string()));
             }
@@ -256,7 +383,7 @@ impl Store {
     pub(crate) fn init_globals(
         &mut self,
         mut imported_globals: Vec<GlobalAddr>,
-        new_globals: Vec<Global>,
+        new_globals: &[Global],
         func_addrs: &[FuncAddr],
         idx: ModuleInstanceAddr,
     ) -> Result<Vec<Addr>> {
@@ -360,12 +487,12 @@ impl Store {
     pub(crate) fn init_datas(
         &mut self,
         mem_addrs: &[MemAddr],
-        datas: Vec<Data>,
+        datas: &[Data],
         idx: ModuleInstanceAddr,
     ) -> Result<(Box<[Addr]>, Option<Trap>)> {
         let data_count = self.data.datas.len();
         let mut data_addrs = Vec::with_capacity(data_count);
-        for (i, data) in datas.into_iter().enumerate() {
+        for (i, data) in datas.iter().enumerate() {
             let data_val = match data.kind {
                 tinywasm_types::DataKind::Active { mem: mem_addr, offset } => {
                     let Some(mem_addr) = mem_addrs.get(mem_addr as usize) else {
@@ -0,0 +1,173 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use tinywasm_types::{ExternAddr, GlobalAddr, MemAddr, ModuleInstanceAddr, TableAddr, ValType};
+
+use super::{Store, StoreData, TableElement};
+use crate::interpreter::TinyWasmValue;
+use crate::preinit::data_segments;
+use crate::{Error, ModuleInstance, Result};
+
+/// The state of a module instance right after instantiation
+///
+/// Only contains state owned by the instance, imported memories, tables and globals are not included.
+/// Memories are stored as their non-zero ranges, so memory that was never written doesn't take up space.
+#[derive(Debug, Default)]
+pub(crate) struct InstanceStates(BTreeMap<ModuleInstanceAddr, InstanceState>);
+
+#[derive(Debug)]
+struct InstanceState {
+    memories: Vec<MemoryState>,
+    globals: Vec<(GlobalAddr, TinyWasmValue)>,
+    tables: Vec<(TableAddr, Vec<TableElement>)>,
+    elements: Vec<Option<Vec<TableElement>>>,
+    datas: Vec<Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+struct MemoryState {
+    addr: MemAddr,
+    page_count: usize,
+    dirty_epoch: Option<usize>, // only the dirty pages need to be restored if they weren't cleared since
+    segments: Vec<(usize, Vec<u8>)>, // non-zero ranges
+}
+
+impl InstanceStates {
+    // function references in the captured tables and element segments of an instance
+    pub(crate) fn func_refs(&self, addr: ModuleInstanceAddr) -> impl Iterator<Item = &TableElement> {
+        let state = self.0.get(&addr);
+        let tables = state.into_iter().flat_map(|state| state.tables.iter().flat_map(|(_, elements)| elements));
+        let elements = state.into_iter().flat_map(|state| state.elements.iter().flatten().flatten());
+        tables.chain(elements)
+    }
+
+    // references to host objects in the captured tables and globals of all instances
+    pub(crate) fn extern_refs<'a>(&'a self, data: &'a StoreData) -> impl Iterator<Item = ExternAddr> + 'a {
+        self.0.values().flat_map(move |state| {
+            let tables = (state.tables.iter())
+                .filter(|(addr, _)| data.tables[*addr as usize].kind.element_type == ValType::RefExtern)
+                .flat_map(|(_, elements)| elements.iter().filter_map(TableElement::addr));
+            let globals = (state.globals.iter())
+                .filter(|(addr, _)| data.globals[*addr as usize].ty.ty == ValType::RefExtern)
+                .filter_map(|(_, value)| value.unwrap_ref());
+            tables.chain(globals)
+        })
+    }
+
+    pub(crate) fn remove(&mut self, addr: ModuleInstanceAddr) {
+        self.0.remove(&addr);
+    }
+}
+
+impl Store {
+    /// Remember the state of instances created from now on, so they can be restored using [`ModuleInstance::reset`]
+    ///
+    /// Capturing the state copies the non-zero parts of the instance's memories and its tables when it is instantiated,
+    /// so this is disabled by default.
+    pub fn enable_instance_reset(&mut self) {
+        self.instance_reset = true;
+    }
+
+    // remember the current state of an instance, so it can be restored by `ModuleInstance::reset`
+    pub(crate) fn capture_instance_state(&mut self, instance: &ModuleInstance) {
+        if !self.instance_reset {
+            return;
+        }
+
+        let (data, idx) = (&self.data, instance.id());
+        let memories = (instance.0.mem_addrs.iter().map(|addr| (*addr, &data.memories[*addr as usize])))
+            .filter(|(_, mem)| mem._owner == idx)
+            .map(|(addr, mem)| {
+                let segments = data_segments(&mem.data).into_iter().map(|(offset, bytes)| (offset, bytes.to_vec()));
+                let dirty_epoch = mem.dirty.as_ref().map(|dirty| dirty.epoch);
+                MemoryState { addr, page_count: mem.page_count, dirty_epoch, segments: segments.collect() }
+            });
+
+        let globals = (instance.0.global_addrs.iter().map(|addr| (*addr, &data.globals[*addr as usize])))
+            .filter(|(_, global)| global._owner == idx && global.ty.mutable)
+            .map(|(addr, global)| (addr, global.value.get()));
+
+        let tables = (instance.0.table_addrs.iter().map(|addr| (*addr, &data.tables[*addr as usize])))
+            .filter(|(_, table)| table._owner == idx)
+            .map(|(addr, table)| (addr, table.elements.clone()));
+
+        let state = InstanceState {
+            memories: memories.collect(),
+            globals: globals.collect(),
+            tables: tables.collect(),
+            elements: instance.0.elem_addrs.iter().map(|addr| data.elements[*addr as usize].items.clone()).collect(),
+            datas: instance.0.data_addrs.iter().map(|addr| data.datas[*addr as usize].data.clone()).collect(),
+        };
+
+        self.instance_states.0.insert(idx, state);
+    }
+
+    // restore the state captured by `capture_instance_state`
+    pub(crate) fn reset_instance(&mut self, instance: &ModuleInstance) -> Result<()> {
+        let Some(state) = self.instance_states.0.get_mut(&instance.id()) else {
+            return Err(Error::Other(
+                "instance has no initial state to reset to, see `Store::enable_instance_reset`".into(),
+            ));
+        };
+
+        let data = &mut self.data;
+        for state in &mut state.memories {
+            let mem = &mut data.memories[state.addr as usize];
+            let dirty_only = state.dirty_epoch.is_some() && state.dirty_epoch == mem.dirty.as_ref().map(|d| d.epoch);
+            mem.reset(state.page_count, &state.segments, dirty_only);
+            state.dirty_epoch = mem.dirty.as_ref().map(|dirty| dirty.epoch);
+        }
+
+        for (addr, value) in &state.globals {
+            data.globals[*addr as usize].value.set(*value);
+        }
+
+        for (addr, elements) in &state.tables {
+            data.tables[*addr as usize].elements.clone_from(elements);
+        }
+
+        for (addr, items) in instance.0.elem_addrs.iter().zip(&state.elements) {
+            data.elements[*addr as usize].items.clone_from(items);
+        }
+
+        for (addr, bytes) in instance.0.data_addrs.iter().zip(&state.datas) {
+            data.datas[*addr as usize].data.clone_from(bytes);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "parser"))]
+mod reset_tests {
+    use crate::test_util::module;
+    use crate::Store;
+
+    const COUNTER: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $count (export "count") (mut i32) (i32.const 0))
+        (func (export "inc") (result i32)
+            (global.set $count (i32.add (global.get $count) (i32.const 1)))
+            (i32.store (i32.const 0) (global.get $count))
+            (global.get $count)))"#;
+
+    #[test]
+    fn test_reset() {
+        let module = module(COUNTER);
+
+        // instances can't be reset unless enabled before instantiating them
+        let mut store = Store::default();
+        let instance = module.clone().instantiate(&mut store, None).unwrap();
+        assert!(store.instance_states.0.is_empty());
+        assert!(instance.reset(&mut store).is_err());
+
+        let mut store = Store::default();
+        store.enable_instance_reset();
+        let instance = module.instantiate(&mut store, None).unwrap();
+        let inc = instance.exported_func::<(), i32>(&store, "inc").unwrap();
+        assert_eq!(inc.call(&mut store, ()).unwrap(), 1);
+        assert_eq!(inc.call(&mut store, ()).unwrap(), 2);
+
+        instance.reset(&mut store).unwrap();
+        assert_eq!(instance.exported_memory(&mut store, "memory").unwrap().load(0, 4).unwrap(), [0; 4]);
+        assert_eq!(inc.call(&mut store, ()).unwrap(), 1);
+    }
+}
@@ -0,0 +1,307 @@
+use alloc::{format, vec::Vec};
+
+use super::{Store, TableElement};
+use crate::interpreter::TinyWasmValue;
+use crate::{Error, Result};
+use tinywasm_types::ValType;
+
+/// A snapshot of all guest-visible state of a [`Store`]
+///
+/// Contains the contents of all memories, tables and globals, and the state of all data and element segments.
+/// Function instances and host state are not included, so a snapshot can only be restored
+/// into a store with the same modules instantiated in the same order.
+/// Host objects referenced by `externref` values aren't included either, so these references
+/// are only meaningful in the store that created the snapshot.
+///
+/// See [`Store::snapshot`] and [`Store::restore`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
+pub struct Snapshot {
+    memories: Vec<Vec<u8>>,
+    globals: Vec<GlobalValue>,
+    tables: Vec<Vec<Option<u32>>>,
+    elements: Vec<Option<Vec<Option<u32>>>>, // none if the element segment was dropped
+    datas: Vec<Option<Vec<u8>>>,             // none if the data segment was dropped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
+enum GlobalValue {
+    Value32(u32),
+    Value64(u64),
+    Value128(u128),
+    ValueRef(Option<u32>),
+}
+
+impl From<TinyWasmValue> for GlobalValue {
+    fn from(value: TinyWasmValue) -> Self {
+        match value {
+            TinyWasmValue::Value32(v) => Self::Value32(v),
+            TinyWasmValue::Value64(v) => Self::Value64(v),
+            TinyWasmValue::Value128(v) => Self::Value128(v),
+            TinyWasmValue::ValueRef(v) => Self::ValueRef(v),
+        }
+    }
+}
+
+impl From<GlobalValue> for TinyWasmValue {
+    fn from(value: GlobalValue) -> Self {
+        match value {
+            GlobalValue::Value32(v) => Self::Value32(v),
+            GlobalValue::Value64(v) => Self::Value64(v),
+            GlobalValue::Value128(v) => Self::Value128(v),
+            GlobalValue::ValueRef(v) => Self::ValueRef(v),
+        }
+    }
+}
+
+fn to_elements(elements: &[TableElement]) -> Vec<Option<u32>> {
+    elements.iter().map(TableElement::addr).collect()
+}
+
+fn from_elements(elements: &[Option<u32>]) -> Vec<TableElement> {
+    elements.iter().map(|addr| TableElement::from(*addr)).collect()
+}
+
+fn mismatch(what: &str) -> Error {
+    Error::Other(format!("snapshot does not match the store: {what}"))
+}
+
+impl Store {
+    /// Capture the current state of all memories, tables, globals and data/element segments
+    pub fn snapshot(&self) -> Snapshot {
+        let data = &self.data;
+        Snapshot {
+            memories: data.memories.iter().map(|mem| mem.data.clone()).collect(),
+            globals: data.globals.iter().map(|global| global.value.get().into()).collect(),
+            tables: data.tables.iter().map(|table| to_elements(&table.elements)).collect(),
+            elements: data.elements.iter().map(|elem| elem.items.as_deref().map(to_elements)).collect(),
+            datas: data.datas.iter().map(|data| data.data.clone()).collect(),
+        }
+    }
+
+    /// Restore the state captured by [`Store::snapshot`]
+    ///
+    /// The snapshot can come from a different store, as long as the same modules were instantiated in the same order.
+    /// References to host objects that were never created in this store are rejected, but references to objects
+    /// of a different store can't always be detected.
+    /// The store is left unchanged if the snapshot doesn't match.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.validate_snapshot(snapshot)?;
+        let data = &mut self.data;
+
+        for (mem, bytes) in data.memories.iter_mut().zip(&snapshot.memories) {
+            mem.data.clear();
+            mem.data.extend_from_slice(bytes);
+            mem.page_count = (bytes.len() as u64 / mem.kind.page_size()) as usize;
+            mem.mark_dirty(0, bytes.len());
+        }
+
+        for (global, value) in data.globals.iter().zip(&snapshot.globals) {
+            global.value.set((*value).into());
+        }
+
+        for (table, elements) in data.tables.iter_mut().zip(&snapshot.tables) {
+            table.elements = from_elements(elements);
+        }
+
+        for (elem, items) in data.elements.iter_mut().zip(&snapshot.elements) {
+            elem.items = items.as_deref().map(from_elements);
+        }
+
+        for (data, bytes) in data.datas.iter_mut().zip(&snapshot.datas) {
+            data.data.clone_from(bytes);
+        }
+
+        Ok(())
+    }
+
+    fn validate_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        let data = &self.data;
+        if data.memories.len() != snapshot.memories.len()
+            || data.globals.len() != snapshot.globals.len()
+            || data.tables.len() != snapshot.tables.len()
+            || data.elements.len() != snapshot.elements.len()
+            || data.datas.len() != snapshot.datas.len()
+        {
+            return Err(mismatch("different number of instances"));
+        }
+
+        for (mem, bytes) in data.memories.iter().zip(&snapshot.memories) {
+            let page_size = mem.kind.page_size();
+            let pages = bytes.len() as u64 / page_size;
+            if bytes.len() as u64 % page_size != 0
+                || pages > mem.kind.page_count_max()
+                || pages < mem.kind.page_count_initial()
+            {
+                return Err(mismatch("invalid memory size"));
+            }
+        }
+
+        // function references have to point to functions in this store, extern references to its host objects
+        let is_func = |addr: &Option<u32>| addr.map_or(true, |addr| (addr as usize) < data.funcs.len());
+        let is_extern = |addr: &Option<u32>| addr.map_or(true, |addr| self.extern_refs.is_allocated(addr));
+        let is_valid = |ty: ValType, addr: &Option<u32>| match ty {
+            ValType::RefFunc => is_func(addr),
+            ValType::RefExtern => is_extern(addr),
+            _ => true,
+        };
+
+        for (global, value) in data.globals.iter().zip(&snapshot.globals) {
+            let current = GlobalValue::from(global.value.get());
+            if core::mem::discriminant(&current) != core::mem::discriminant(value) {
+                return Err(mismatch("invalid global type"));
+            }
+            if let GlobalValue::ValueRef(addr) = value {
+                if !is_valid(global.ty.ty, addr) {
+                    return Err(mismatch("invalid reference"));
+                }
+            }
+        }
+
+        for (table, elements) in data.tables.iter().zip(&snapshot.tables) {
+            let len = elements.len() as u32;
+            if len < table.kind.size_initial || table.kind.size_max.is_some_and(|max| len > max) {
+                return Err(mismatch("invalid table size"));
+            }
+            if !elements.iter().all(|addr| is_valid(table.kind.element_type, addr)) {
+                return Err(mismatch("invalid reference"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "archive")]
+mod archive {
+    use super::Snapshot;
+    use crate::{Error, Result};
+    use rkyv::ser::{serializers::AllocSerializer, Serializer};
+    use rkyv::{check_archived_root, AlignedVec, Deserialize};
+
+    const SNAPSHOT_MAGIC: [u8; 16] = *b"TWSS01\0\0\0\0\0\0\0\0\0\0";
+
+    impl Snapshot {
+        /// Serialize the snapshot into a vector of bytes. Requires `archive` feature.
+        pub fn serialize(&self) -> AlignedVec {
+            let mut serializer = AllocSerializer::<0>::default();
+            serializer.pad(SNAPSHOT_MAGIC.len()).unwrap();
+            serializer.serialize_value(self).unwrap();
+            let mut out = serializer.into_serializer().into_inner();
+            out[..SNAPSHOT_MAGIC.len()].copy_from_slice(&SNAPSHOT_MAGIC);
+            out
+        }
+
+        /// Deserialize a snapshot created by [`Snapshot::serialize`]. Requires `archive` feature.
+        pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+            if bytes.len() < SNAPSHOT_MAGIC.len() || bytes[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+                return Err(Error::Other("invalid snapshot: invalid magic number".into()));
+            }
+
+            let root = check_archived_root::<Self>(&bytes[SNAPSHOT_MAGIC.len()..])
+                .map_err(|_e| Error::Other("invalid snapshot: invalid archive".into()))?;
+            Ok(root.deserialize(&mut rkyv::Infallible).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+    use crate::store::{DataInstance, MemoryInstance};
+    use tinywasm_types::{MemoryArch, MemoryType};
+
+    fn create_test_store() -> Store {
+        let mut store = Store::default();
+        let kind = MemoryType::new(MemoryArch::I32, 1, Some(2), None);
+        store.data.memories.push(MemoryInstance::new(kind, 0));
+        store.data.datas.push(DataInstance::new(Some(alloc::vec![1, 2, 3]), 0));
+        store
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut store = create_test_store();
+        store.data.memories[0].store(0, 2, &[1, 2]).unwrap();
+        let snapshot = store.snapshot();
+
+        store.data.memories[0].store(0, 2, &[3, 4]).unwrap();
+        store.data.memories[0].grow(1).unwrap();
+        store.data.datas[0].drop();
+
+        store.restore(&snapshot).unwrap();
+        assert_eq!(store.data.memories[0].load(0, 2).unwrap(), &[1, 2]);
+        assert_eq!(store.data.memories[0].page_count, 1);
+        assert_eq!(store.data.datas[0].data.as_deref(), Some(&[1, 2, 3][..]));
+        assert!(Store::default().restore(&snapshot).is_err());
+
+        #[cfg(feature = "archive")]
+        assert_eq!(Snapshot::deserialize(&snapshot.serialize()).unwrap(), snapshot);
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_invalid_func_refs() {
+        let module = crate::test_util::module(
+            r#"(module
+                (func $f)
+                (table 1 funcref)
+                (elem (i32.const 0) $f)
+                (global (mut funcref) (ref.func $f)))"#,
+        );
+        let mut store = Store::default();
+        module.instantiate(&mut store, None).unwrap();
+        let snapshot = store.snapshot();
+        store.restore(&snapshot).unwrap();
+
+        let mut invalid = snapshot.clone();
+        invalid.tables[0][0] = Some(store.data.funcs.len() as u32);
+        assert!(store.restore(&invalid).is_err());
+
+        let mut invalid = snapshot.clone();
+        invalid.globals[0] = GlobalValue::ValueRef(Some(u32::MAX));
+        assert!(store.restore(&invalid).is_err());
+
+        // null references are always valid
+        let mut valid = snapshot;
+        valid.tables[0][0] = None;
+        valid.globals[0] = GlobalValue::ValueRef(None);
+        store.restore(&valid).unwrap();
+        assert_eq!(store.data.tables[0].elements[0].addr(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "parser")]
+    fn test_invalid_extern_refs() {
+        let module = crate::test_util::module(
+            r#"(module
+                (table (export "table") 1 externref)
+                (global (export "global") (mut externref) (ref.null extern)))"#,
+        );
+        let mut store = Store::default();
+        let instance = module.clone().instantiate(&mut store, None).unwrap();
+        let object = store.new_extern_ref(1u32).unwrap();
+        instance.exported_table(&mut store, "table").unwrap().set(0, object.into()).unwrap();
+        instance.exported_global(&mut store, "global").unwrap().set(object).unwrap();
+        let snapshot = store.snapshot();
+
+        // references to removed objects stay valid
+        store.remove_extern_ref(object);
+        store.restore(&snapshot).unwrap();
+
+        // the objects don't exist in a new store
+        let mut other = Store::default();
+        module.instantiate(&mut other, None).unwrap();
+        assert!(other.restore(&snapshot).is_err());
+        other.new_extern_ref(2u32).unwrap();
+        other.restore(&snapshot).unwrap();
+
+        let mut invalid = snapshot.clone();
+        invalid.tables[0][0] = Some(1);
+        assert!(store.restore(&invalid).is_err());
+        let mut invalid = snapshot;
+        invalid.globals[0] = GlobalValue::ValueRef(Some(1));
+        assert!(store.restore(&invalid).is_err());
+    }
+}
@@ -36,8 +36,7 @@ impl TableInstance {
         })
     }
 
-    pub(crate) fn fill(&mut self, func_addrs: &[u32], addr: usize, len: usize, val: TableElement) -> Result<()> {
-        let val = val.map(|addr| self.resolve_func_ref(func_addrs, addr));
+    pub(crate) fn fill(&mut self, addr: usize, len: usize, val: TableElement) -> Result<()> {
         let end = addr.checked_add(len).ok_or_else(|| self.trap_oob(addr, len))?;
         if end > self.elements.len() {
             return Err(self.trap_oob(addr, len));
@@ -123,16 +122,6 @@ impl TableInstance {
         self.elements.len() as i32
     }
 
-    fn resolve_func_ref(&self, func_addrs: &[u32], addr: Addr) -> Addr {
-        if self.kind.element_type != ValType::RefFunc {
-            return addr;
-        }
-
-        *func_addrs
-            .get(addr as usize)
-            .expect("error initializing table: function not found. This should have been caught by the validator")
-    }
-
     pub(crate) fn init(&mut self, offset: i32, init: &[TableElement]) -> Result<()> {
         let offset = offset as usize;
         let end = offset.checked_add(init.len()).ok_or_else(|| {
@@ -171,13 +160,6 @@ impl TableElement {
             TableElement::Initialized(addr) => Some(*addr),
         }
     }
-
-    pub(crate) fn map<F: FnOnce(Addr) -> Addr>(self, f: F) -> Self {
-        match self {
-            TableElement::Uninitialized => TableElement::Uninitialized,
-            TableElement::Initialized(addr) => TableElement::Initialized(f(addr)),
-        }
-    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,15 @@
+use alloc::vec::Vec;
+
+use crate::Module;
+
+/// Compile the WebAssembly text format to a binary module
+pub(crate) fn wat(source: &str) -> Vec<u8> {
+    let buf = wast::parser::ParseBuffer::new(source).expect("invalid wat");
+    let mut wat = wast::parser::parse::<wast::Wat<'_>>(&buf).expect("invalid wat");
+    wat.encode().expect("failed to encode wat")
+}
+
+/// Compile and parse a module from the WebAssembly text format
+pub(crate) fn module(source: &str) -> Module {
+    Module::parse_bytes(&wat(source)).expect("invalid module")
+}
@@ -0,0 +1,67 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use eyre::Result;
+use tinywasm::{Extern, ExternRef, FuncContext, Imports, Module, Store};
+
+// counts all allocations, this is the only test in this binary so nothing else allocates concurrently
+struct CountingAlloc;
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const WAT: &str = r#"(module
+    (import "env" "wrap" (func $wrap (param i32 i64) (result i64)))
+    (import "env" "typed" (func $typed (param i32 i64) (result i64)))
+    (import "env" "object" (func $object (param externref) (result externref)))
+    (func (export "run") (param i32 externref) (result i64) (local i64)
+        (loop $loop
+            (local.set 2 (call $wrap (local.get 0) (local.get 2)))
+            (local.set 2 (call $typed (local.get 0) (local.get 2)))
+            (local.set 1 (call $object (local.get 1)))
+            (br_if $loop (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+        (local.get 2)))"#;
+
+#[test]
+fn test_typed_host_calls_dont_allocate() -> Result<()> {
+    let buf = wast::parser::ParseBuffer::new(WAT)?;
+    let module = Module::parse_bytes(&wast::parser::parse::<wast::Wat<'_>>(&buf)?.encode()?)?;
+    let mut imports = Imports::new();
+    imports.define("env", "wrap", Extern::wrap(|a: i32, b: i64| b + a as i64))?;
+    imports.define("env", "typed", Extern::typed_func(|_: FuncContext<'_>, (a, b): (i32, i64)| Ok(b + a as i64)))?;
+    imports.define("env", "object", Extern::wrap(|r: Option<ExternRef>| r))?;
+
+    let mut store = Store::default();
+    let instance = module.instantiate(&mut store, Some(imports))?;
+    let run = instance.exported_func::<(i32, Option<ExternRef>), i64>(&store, "run")?;
+
+    let allocations = |store: &mut Store, n: i32, object: Option<ExternRef>| -> Result<usize> {
+        let start = ALLOCATIONS.load(Ordering::Relaxed);
+        assert_eq!(run.call(store, (n, object))?, n as i64 * (n as i64 + 1));
+        Ok(ALLOCATIONS.load(Ordering::Relaxed) - start)
+    };
+
+    // the first call grows the stacks
+    allocations(&mut store, 1000, None)?;
+
+    // the number of allocations doesn't depend on the number of host calls
+    assert_eq!(allocations(&mut store, 1, None)?, allocations(&mut store, 1000, None)?);
+
+    // also not if references of the calling code have to be kept during host calls
+    let object = Some(store.new_extern_ref(1u32)?);
+    allocations(&mut store, 1000, object)?;
+    assert_eq!(allocations(&mut store, 1, object)?, allocations(&mut store, 1000, object)?);
+    Ok(())
+}
@@ -8,7 +8,7 @@ use rkyv::{
 };
 
 const TWASM_MAGIC_PREFIX: &[u8; 4] = b"TWAS";
-const TWASM_VERSION: &[u8; 2] = b"01";
+const TWASM_VERSION: &[u8; 2] = b"02";
 #[rustfmt::skip]
 const TWASM_MAGIC: [u8; 16] = [ TWASM_MAGIC_PREFIX[0], TWASM_MAGIC_PREFIX[1], TWASM_MAGIC_PREFIX[2], TWASM_MAGIC_PREFIX[3], TWASM_VERSION[0], TWASM_VERSION[1], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
 
@@ -76,6 +76,8 @@ impl TinyWasmModule {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Instruction, WasmFunction};
+    use alloc::boxed::Box;
 
     #[test]
     fn test_serialize() {
@@ -84,4 +86,16 @@ mod tests {
         let wasm2 = TinyWasmModule::from_twasm(&twasm).unwrap();
         assert_eq!(wasm, wasm2);
     }
+
+    #[test]
+    fn test_serialize_offsets() {
+        let func = WasmFunction {
+            instructions: Box::new([Instruction::Nop, Instruction::Return]),
+            offsets: Box::new([0x21, 0x22]),
+            ..Default::default()
+        };
+        let wasm = TinyWasmModule { funcs: Box::new([func]), ..Default::default() };
+        let wasm2 = TinyWasmModule::from_twasm(&wasm.serialize_twasm()).unwrap();
+        assert_eq!(wasm2.funcs[0].instruction_offset(1), Some(0x22));
+    }
 }
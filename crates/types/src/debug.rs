@@ -0,0 +1,98 @@
+use alloc::boxed::Box;
+use core::fmt::{Display, Formatter};
+
+/// A mapping from code offsets to guest source locations
+///
+/// Built from the `.debug_line` and `.debug_info` DWARF sections of the original WebAssembly module.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
+pub struct LineTable {
+    /// Source file paths, indexed by [`LineRow::file`]
+    pub files: Box<[Box<str>]>,
+
+    /// Line table rows, sorted by address
+    pub rows: Box<[LineRow]>,
+}
+
+/// A single row of a [`LineTable`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
+pub struct LineRow {
+    /// Offset of the first byte covered by this row, relative to the start of the module
+    pub address: u32,
+    /// Index into [`LineTable::files`]
+    pub file: u32,
+    /// 1-based line number, 0 if unknown
+    pub line: u32,
+    /// 1-based column number, 0 if unknown
+    pub column: u32,
+    /// Marks the first address after the end of a sequence of rows
+    pub end_sequence: bool,
+}
+
+/// A location in the guest's source code
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    /// Path of the source file
+    pub file: Box<str>,
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column number, 0 if unknown
+    pub column: u32,
+}
+
+impl LineTable {
+    /// Create a new line table, sorting the rows by address
+    pub fn new(files: Box<[Box<str>]>, mut rows: alloc::vec::Vec<LineRow>) -> Self {
+        // the start of a sequence has to come after the end of the previous one if they share an address
+        rows.sort_by_key(|row| (row.address, !row.end_sequence));
+        Self { files, rows: rows.into_boxed_slice() }
+    }
+
+    /// Find the source location of the given module offset
+    pub fn lookup(&self, offset: u32) -> Option<SourceLocation> {
+        let idx = self.rows.partition_point(|row| row.address <= offset).checked_sub(1)?;
+        let row = &self.rows[idx];
+        if row.end_sequence || row.line == 0 {
+            return None;
+        }
+
+        let file = self.files.get(row.file as usize)?.clone();
+        Some(SourceLocation { file, line: row.line, column: row.column })
+    }
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.column {
+            0 => write!(f, "{}:{}", self.file, self.line),
+            column => write!(f, "{}:{}:{}", self.file, self.line, column),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec};
+
+    fn row(address: u32, line: u32, end_sequence: bool) -> LineRow {
+        LineRow { address, file: 0, line, column: 0, end_sequence }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let files = vec![Box::from("main.c")].into_boxed_slice();
+        let table = LineTable::new(
+            files,
+            vec![row(20, 1, false), row(30, 0, true), row(10, 7, false), row(14, 8, false), row(20, 0, true)],
+        );
+
+        assert_eq!(table.lookup(5), None);
+        assert_eq!(table.lookup(10).map(|l| l.line), Some(7));
+        assert_eq!(table.lookup(19).map(|l| l.line), Some(8));
+        assert_eq!(table.lookup(20).map(|l| l.line), Some(1));
+        assert_eq!(table.lookup(30), None);
+        assert_eq!(table.lookup(10).unwrap().to_string(), "main.c:7");
+    }
+}
@@ -37,8 +37,10 @@ pub(crate) mod log {
     pub(crate) use info;
 }
 
+mod debug;
 mod instructions;
 mod value;
+pub use debug::*;
 pub use instructions::*;
 pub use value::*;
 
@@ -102,6 +104,24 @@ pub struct TinyWasmModule {
     ///
     /// Corresponds to the `elem` section of the original WebAssembly module.
     pub elements: Box<[Element]>,
+
+    /// Source line information used to resolve trap locations.
+    ///
+    /// Corresponds to the `.debug_line` and `.debug_info` custom sections of the original WebAssembly module.
+    pub line_table: Option<LineTable>,
+
+    /// Function names, sorted by function index.
+    ///
+    /// Corresponds to the function names subsection of the `name` custom section of the original WebAssembly module.
+    pub func_names: Box<[(FuncAddr, Box<str>)]>,
+}
+
+impl TinyWasmModule {
+    /// Get the name of a function from the `name` section, if present
+    pub fn func_name(&self, func_idx: FuncAddr) -> Option<&str> {
+        let idx = self.func_names.binary_search_by_key(&func_idx, |(idx, _)| *idx).ok()?;
+        Some(&self.func_names[idx].1)
+    }
 }
 
 /// A WebAssembly External Kind.
@@ -207,11 +227,24 @@ pub struct ValueCountsSmall {
 #[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
 pub struct WasmFunction {
     pub instructions: Box<[Instruction]>,
+    /// Byte offset of each instruction in the original WebAssembly module.
+    ///
+    /// Empty if the offsets were not recorded.
+    pub offsets: Box<[u32]>,
     pub locals: ValueCounts,
     pub params: ValueCountsSmall,
     pub ty: FuncType,
 }
 
+impl WasmFunction {
+    /// Get the byte offset of the instruction at `instr_idx` in the original WebAssembly module
+    ///
+    /// Instructions fused from multiple operators report the offset of the first one.
+    pub fn instruction_offset(&self, instr_idx: usize) -> Option<u32> {
+        self.offsets.get(instr_idx).copied()
+    }
+}
+
 /// A WebAssembly Module Export
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "archive", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize), archive(check_bytes))]
@@ -275,6 +308,10 @@ impl MemoryType {
         self.arch
     }
 
+    pub fn with_page_count_initial(self, page_count_initial: u64) -> Self {
+        Self { page_count_initial, ..self }
+    }
+
     pub fn page_count_initial(&self) -> u64 {
         self.page_count_initial
     }